@@ -0,0 +1,120 @@
+//! Waking futures at deadlines, with a single thread shared by all of them.
+
+use std::{
+    collections::HashMap,
+    sync::{Condvar, Mutex, MutexGuard, OnceLock},
+    task::Waker,
+    thread,
+    time::Instant,
+};
+
+struct Pending {
+    deadline: Instant,
+    waker:    Waker,
+}
+
+struct Inner {
+    pending: HashMap<u64, Pending>,
+    next_id: u64,
+    /// Timer thread was spawned
+    running: bool,
+}
+
+struct Timer {
+    inner:   Mutex<Inner>,
+    changed: Condvar,
+}
+
+static TIMER: OnceLock<Timer> = OnceLock::new();
+
+fn timer() -> &'static Timer {
+    TIMER.get_or_init(|| {
+        Timer {
+            inner:   Mutex::new(Inner {
+                pending: HashMap::new(),
+                next_id: 0,
+                running: false,
+            }),
+            changed: Condvar::new(),
+        }
+    })
+}
+
+impl Timer {
+    fn lock(&self) -> MutexGuard<'_, Inner> { self.inner.lock().unwrap() }
+
+    /// Body of the timer thread, waking due alarms forever
+    fn run(&self) {
+        let mut inner = self.lock();
+        loop {
+            let now = Instant::now();
+            let due: Vec<u64> = inner
+                .pending
+                .iter()
+                .filter(|(_, pending)| pending.deadline <= now)
+                .map(|(id, _)| *id)
+                .collect();
+            if !due.is_empty() {
+                let wakers: Vec<Waker> = due
+                    .iter()
+                    .filter_map(|id| inner.pending.remove(id))
+                    .map(|pending| pending.waker)
+                    .collect();
+                // Outside of the lock, as waking may run executor code
+                drop(inner);
+                wakers.into_iter().for_each(Waker::wake);
+                inner = self.lock();
+                continue;
+            }
+            let next = inner.pending.values().map(|pending| pending.deadline).min();
+            inner = match next {
+                Some(deadline) => self.changed.wait_timeout(inner, deadline - now).unwrap().0,
+                None => self.changed.wait(inner).unwrap(),
+            };
+        }
+    }
+}
+
+/// Single rearmable alarm. Dropping it cancels it.
+pub(crate) struct Alarm {
+    id: u64,
+}
+
+impl Alarm {
+    pub(crate) fn new() -> Self {
+        let mut inner = timer().lock();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        Self {
+            id,
+        }
+    }
+
+    /// Wake `waker` at `deadline`, replacing whatever alarm was set before
+    pub(crate) fn set(&self, deadline: Instant, waker: Waker) {
+        let timer = timer();
+        let mut inner = timer.lock();
+        inner.pending.insert(
+            self.id,
+            Pending {
+                deadline,
+                waker,
+            },
+        );
+        if !inner.running {
+            inner.running = true;
+            thread::Builder::new()
+                .name("pakr-fsm-timer".to_string())
+                .spawn(move || timer.run())
+                .expect("failed to spawn thread");
+        }
+        timer.changed.notify_one();
+    }
+
+    /// Cancel alarm, if set
+    pub(crate) fn cancel(&self) { timer().lock().pending.remove(&self.id); }
+}
+
+impl Drop for Alarm {
+    fn drop(&mut self) { self.cancel() }
+}
@@ -0,0 +1,385 @@
+//! Executor-agnostic variant of [`Reactor`](crate::Reactor).
+
+use crate::{alarm::Alarm, Clock, Machine, ReactorError, StepOutcome, SystemClock, FSM};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Instant,
+};
+
+type JoinResult<F> = Result<Option<<F as FSM>::Response>, ReactorError<<F as FSM>::Event>>;
+
+/// Queue shared between senders, driver and join handle
+struct Chan<F: FSM> {
+    events:      VecDeque<F::Event>,
+    senders:     usize,
+    closed:      bool,
+    result:      Option<JoinResult<F>>,
+    driver_wake: Option<Waker>,
+    join_wake:   Option<Waker>,
+}
+
+type Shared<F> = Arc<Mutex<Chan<F>>>;
+
+/// `AsyncReactor` is `FSM` handle to interact with machine running as a
+/// `Future`.
+///
+/// Unlike [`Reactor`](crate::Reactor) it does not spawn any thread. The
+/// machine is run by [`Driver`], that has to be spawned on (or awaited by)
+/// any executor.
+///
+/// # Example
+/// ```
+/// # use pakr_fsm::*;
+/// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// # enum MyEv { E1, E2 }
+/// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// # enum MyState { S1, S2 }
+/// # impl Default for MyState {
+/// #     fn default() -> Self { Self::S1 }
+/// # }
+/// # struct MyFSM;
+/// # impl FSM for MyFSM {
+/// #     type Event = MyEv;
+/// #     type Response = &'static str;
+/// #     type State = MyState;
+/// #     fn new() -> Self { Self {} }
+/// #     fn trasnsit(
+/// #         &mut self,
+/// #         old_state: &Self::State,
+/// #         ev: &Self::Event,
+/// #     ) -> (Option<Self::State>, Option<Self::Response>) {
+/// #         match (old_state, ev) {
+/// #             (MyState::S1, MyEv::E1) => (None, Some("Quitting")),
+/// #             (MyState::S1, MyEv::E2) => (Some(MyState::S2), None),
+/// #             (MyState::S2, MyEv::E1) => (Some(MyState::S1), Some("S2@E1->S1")),
+/// #             (MyState::S2, MyEv::E2) => (Some(MyState::S2), Some("S2@E2->S2")),
+/// #         }
+/// #     }
+/// #     fn respond(&mut self, _: &MyState, _: &Option<MyState>, _: &&'static str) {}
+/// # }
+/// use pakr_fsm::executor::LocalPool;
+///
+/// let (fsm, driver) = AsyncReactor::<MyFSM>::new();
+///
+/// let mut pool = LocalPool::new();
+/// pool.spawn(driver);
+/// let ans = pool.run_until(async move {
+///     fsm.send(MyEv::E2).await.unwrap();
+///     fsm.send(MyEv::E1).await.unwrap();
+///     fsm.send(MyEv::E1).await.unwrap();
+///     fsm.join().await
+/// });
+/// assert_eq!(ans, Ok(Some("Quitting")));
+/// ```
+pub struct AsyncReactor<F: FSM> {
+    chan: AsyncSender<F>,
+}
+
+impl<F: FSM> AsyncReactor<F> {
+    /// Create new `AsyncReactor`.
+    ///
+    /// Initializes associated `FSM` by calling its `new` and setting state to
+    /// `default`. Returned [`Driver`] runs the machine and must be polled to
+    /// completion by some executor.
+    pub fn new() -> (Self, Driver<F>) { Self::with_clock(SystemClock) }
+
+    /// Create new `AsyncReactor` measuring time with `clock`, e.g. a
+    /// [`ManualClock`](crate::ManualClock) in tests.
+    ///
+    /// # Example
+    /// ```
+    /// # use pakr_fsm::*;
+    /// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    /// # enum MyEv { Arm, Fire }
+    /// # #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    /// # enum MyState { #[default] Idle, Armed }
+    /// # #[derive(Default)]
+    /// # struct MyFSM { ctx: Option<Context<MyEv>> }
+    /// # impl FSM for MyFSM {
+    /// #     type Event = MyEv;
+    /// #     type Response = &'static str;
+    /// #     type State = MyState;
+    /// #     fn new() -> Self { Self::default() }
+    /// #     fn trasnsit(&mut self, state: &MyState, ev: &MyEv) -> (Option<MyState>, Option<&'static str>) {
+    /// #         match (state, ev) {
+    /// #             (MyState::Idle, MyEv::Arm) => {
+    /// #                 self.ctx.as_ref().unwrap().schedule(Duration::from_secs(3600), MyEv::Fire);
+    /// #                 (Some(MyState::Armed), None)
+    /// #             }
+    /// #             (MyState::Armed, MyEv::Fire) => (None, Some("fired")),
+    /// #             (state, _) => (Some(*state), None),
+    /// #         }
+    /// #     }
+    /// #     fn respond(&mut self, _: &MyState, _: &Option<MyState>, _: &&'static str) {}
+    /// #     fn attach(&mut self, ctx: Context<MyEv>) { self.ctx = Some(ctx) }
+    /// # }
+    /// use pakr_fsm::executor::LocalPool;
+    /// use std::time::Duration;
+    ///
+    /// let clock = ManualClock::new();
+    /// let (fsm, driver) = AsyncReactor::<MyFSM>::with_clock(clock.clone());
+    /// let _sender = fsm.get_sender();
+    ///
+    /// let mut pool = LocalPool::new();
+    /// pool.spawn(driver);
+    /// pool.run_until(fsm.send(MyEv::Arm)).unwrap();
+    /// // Let driver take the event and arm the timer
+    /// pool.run_until(async {});
+    ///
+    /// // Hour-long timer fires at once, when clock is told so
+    /// clock.advance(Duration::from_secs(3600));
+    /// assert_eq!(pool.run_until(fsm.join()), Ok(Some("fired")));
+    /// ```
+    pub fn with_clock(clock: impl Clock + 'static) -> (Self, Driver<F>) {
+        let clock: Arc<dyn Clock> = Arc::new(clock);
+        let shared = Arc::new(Mutex::new(Chan {
+            events:      VecDeque::new(),
+            senders:     1,
+            closed:      false,
+            result:      None,
+            driver_wake: None,
+            join_wake:   None,
+        }));
+
+        // Let driver recheck timers when clock jumps
+        let woken = Arc::new(Mutex::new(None::<Waker>));
+        let wake: Arc<dyn Fn() + Send + Sync> = {
+            let woken = woken.clone();
+            Arc::new(move || {
+                if let Some(waker) = woken.lock().unwrap().take() {
+                    waker.wake();
+                }
+            })
+        };
+        clock.subscribe(Arc::downgrade(&wake));
+
+        let driver = Driver {
+            machine: Machine::with_clock(F::new(), F::State::default(), clock.clone()),
+            shared: shared.clone(),
+            done: false,
+            clock,
+            alarm: Alarm::new(),
+            woken,
+            _wake: wake,
+        };
+
+        (
+            Self {
+                chan: AsyncSender {
+                    shared,
+                },
+            },
+            driver,
+        )
+    }
+
+    /// Waits for `FSM` to complete.
+    ///
+    /// Releases this handle's end of the event channel first, so the machine
+    /// also completes once all other senders are gone. Returns response of the
+    /// last transition, `MachinePanicked` if machine panicked or `Cancelled` if
+    /// [`Driver`] was dropped before completion.
+    pub fn join(self) -> Join<F> {
+        Join {
+            shared: self.chan.shared.clone(),
+        }
+    }
+
+    /// Send event to `FSM`
+    pub async fn send(&self, ev: <F as FSM>::Event) -> Result<(), ReactorError<<F as FSM>::Event>> {
+        self.chan.send(ev).await
+    }
+
+    /// Clone `send` endpoint of `FSM` channel to be used in other places.
+    pub fn get_sender(&self) -> AsyncSender<F> { self.chan.clone() }
+}
+
+/// Cloneable `send` endpoint of [`AsyncReactor`].
+pub struct AsyncSender<F: FSM> {
+    shared: Shared<F>,
+}
+
+impl<F: FSM> AsyncSender<F> {
+    /// Send event to `FSM`
+    pub async fn send(&self, ev: <F as FSM>::Event) -> Result<(), ReactorError<<F as FSM>::Event>> {
+        let mut chan = self.shared.lock().unwrap();
+        if chan.closed {
+            return Err(ReactorError::MachineTerminated(ev));
+        }
+        chan.events.push_back(ev);
+        if let Some(waker) = chan.driver_wake.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
+impl<F: FSM> Clone for AsyncSender<F> {
+    fn clone(&self) -> Self {
+        self.shared.lock().unwrap().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<F: FSM> Drop for AsyncSender<F> {
+    fn drop(&mut self) {
+        let mut chan = self.shared.lock().unwrap();
+        chan.senders -= 1;
+        if chan.senders == 0 {
+            if let Some(waker) = chan.driver_wake.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// `Future` running the `FSM` of an [`AsyncReactor`].
+///
+/// Completes when machine terminates or all senders are gone, in which case
+/// [`FSM::on_shutdown`] gives the final response. Timers of the
+/// machine are serviced by a single helper thread, shared by all drivers,
+/// waking the driver when they are due by its clock.
+pub struct Driver<F: FSM> {
+    machine: Machine<F>,
+    shared:  Shared<F>,
+    done:    bool,
+    clock:   Arc<dyn Clock>,
+    /// Wakes driver at the earliest deadline of a timer
+    alarm:   Alarm,
+    /// Waker of the driver, for the clock to wake it when it jumps
+    woken:   Arc<Mutex<Option<Waker>>>,
+    /// Keeps clock subscription alive
+    _wake:   Arc<dyn Fn() + Send + Sync>,
+}
+
+// `Driver` is never pin-projected, so moving it is always fine.
+impl<F: FSM> Unpin for Driver<F> {}
+
+impl<F: FSM> Driver<F> {
+    fn finish(&mut self, chan: &mut Chan<F>, result: JoinResult<F>) {
+        self.done = true;
+        chan.closed = true;
+        chan.events.clear();
+        chan.result = Some(result);
+        if let Some(waker) = chan.join_wake.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<F: FSM> Future for Driver<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        let shared = this.shared.clone();
+        *this.woken.lock().unwrap() = Some(cx.waker().clone());
+
+        loop {
+            let ev = match this
+                .machine
+                .take_replayed()
+                .or_else(|| this.machine.take_expired())
+            {
+                Some(ev) => ev,
+                None => {
+                    let mut chan = shared.lock().unwrap();
+                    match chan.events.pop_front() {
+                        Some(ev) => ev,
+                        None if chan.senders == 0 => {
+                            drop(chan);
+                            let machine = &mut this.machine;
+                            let result =
+                                panic::catch_unwind(AssertUnwindSafe(|| machine.shutdown()))
+                                    .map_err(|payload| ReactorError::panicked(&*payload));
+                            this.finish(&mut shared.lock().unwrap(), result);
+                            return Poll::Ready(());
+                        }
+                        None => {
+                            chan.driver_wake = Some(cx.waker().clone());
+                            // Executor knows nothing about timers
+                            let delay = this
+                                .machine
+                                .next_deadline()
+                                .and_then(|deadline| this.clock.wait_for(deadline));
+                            match delay {
+                                Some(delay) => {
+                                    this.alarm.set(Instant::now() + delay, cx.waker().clone())
+                                }
+                                // Clock wakes driver by itself, if it ever moves
+                                None => this.alarm.cancel(),
+                            }
+                            return Poll::Pending;
+                        }
+                    }
+                }
+            };
+
+            let machine = &mut this.machine;
+            let result = panic::catch_unwind(AssertUnwindSafe(move || {
+                match machine.step(ev) {
+                    StepOutcome::Terminated(response) => Some(response),
+                    StepOutcome::Running {
+                        ..
+                    } => None,
+                }
+            }));
+
+            match result {
+                Ok(None) => {}
+                Ok(Some(response)) => {
+                    this.finish(&mut shared.lock().unwrap(), Ok(response));
+                    return Poll::Ready(());
+                }
+                Err(payload) => {
+                    this.finish(
+                        &mut shared.lock().unwrap(),
+                        Err(ReactorError::panicked(&*payload)),
+                    );
+                    return Poll::Ready(());
+                }
+            }
+        }
+    }
+}
+
+impl<F: FSM> Drop for Driver<F> {
+    fn drop(&mut self) {
+        if !self.done {
+            let mut chan = self.shared.lock().unwrap();
+            chan.closed = true;
+            chan.events.clear();
+            if let Some(waker) = chan.join_wake.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// `Future` returned by [`AsyncReactor::join`].
+pub struct Join<F: FSM> {
+    shared: Shared<F>,
+}
+
+impl<F: FSM> Future for Join<F> {
+    type Output = JoinResult<F>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut chan = self.shared.lock().unwrap();
+        if let Some(result) = chan.result.take() {
+            return Poll::Ready(result);
+        }
+        if chan.closed {
+            return Poll::Ready(Err(ReactorError::Cancelled));
+        }
+        chan.join_wake = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
@@ -0,0 +1,275 @@
+//! Configuring [`Reactor`] before it is spawned.
+
+#[cfg(feature = "serde")]
+use crate::Snapshot;
+use crate::{history::Recorder, Clock, DropPolicy, Reactor, Supervisor, SystemClock, FSM};
+#[cfg(feature = "journal")]
+use crate::{journal::Journaling, Journal, Recovered};
+use std::{io, sync::Arc};
+
+/// Builder of [`Reactor`] with non-default settings.
+///
+/// # Example
+/// ```
+/// # use pakr_fsm::*;
+/// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// # enum MyEv { E1, E2 }
+/// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// # enum MyState { S1, S2 }
+/// # impl Default for MyState {
+/// #     fn default() -> Self { Self::S1 }
+/// # }
+/// # struct MyFSM;
+/// # impl FSM for MyFSM {
+/// #     type Event = MyEv;
+/// #     type Response = &'static str;
+/// #     type State = MyState;
+/// #     fn new() -> Self { Self {} }
+/// #     fn trasnsit(
+/// #         &mut self,
+/// #         old_state: &Self::State,
+/// #         ev: &Self::Event,
+/// #     ) -> (Option<Self::State>, Option<Self::Response>) {
+/// #         match (old_state, ev) {
+/// #             (MyState::S1, MyEv::E1) => (None, Some("Quitting")),
+/// #             (MyState::S1, MyEv::E2) => (Some(MyState::S2), None),
+/// #             (MyState::S2, MyEv::E1) => (Some(MyState::S1), Some("S2@E1->S1")),
+/// #             (MyState::S2, MyEv::E2) => (Some(MyState::S2), Some("S2@E2->S2")),
+/// #         }
+/// #     }
+/// #     fn respond(&mut self, _: &MyState, _: &Option<MyState>, _: &&'static str) {}
+/// # }
+/// let fsm = ReactorBuilder::<MyFSM>::new()
+///     .name("door-1")
+///     .stack_size(64 * 1024)
+///     .queue_capacity(16)
+///     .initial_state(MyState::S2)
+///     .history(8)
+///     .spawn()
+///     .unwrap();
+///
+/// assert_eq!(fsm.name(), Some("door-1"));
+/// assert_eq!(fsm.state(), MyState::S2);
+/// assert_eq!(fsm.ask(MyEv::E1), Ok(Some("S2@E1->S1")));
+/// ```
+pub struct ReactorBuilder<F: FSM> {
+    pub(crate) name:       Option<String>,
+    pub(crate) stack_size: Option<usize>,
+    pub(crate) capacity:   Option<usize>,
+    pub(crate) initial:    F::State,
+    pub(crate) clock:      Arc<dyn Clock>,
+    pub(crate) recorder:   Option<Recorder<F>>,
+    pub(crate) supervisor: Option<Supervisor<F>>,
+    pub(crate) on_drop:    DropPolicy,
+    /// Machine to start with instead of a new one
+    pub(crate) fsm:        Option<Box<dyn FnOnce() -> F + Send>>,
+    /// Builds new machines instead of `F::new()`
+    pub(crate) factory:    Option<Factory<F>>,
+    /// Initial state was already entered before
+    pub(crate) resume:     bool,
+    #[cfg(feature = "journal")]
+    pub(crate) journal:    Option<Journaling<F>>,
+}
+
+/// Builder of new machines
+pub(crate) type Factory<F> = Box<dyn FnMut() -> F + Send>;
+
+impl<F: FSM + 'static> ReactorBuilder<F> {
+    /// Builder of default `Reactor`, same as created by [`Reactor::new`]
+    pub fn new() -> Self {
+        #[cfg(feature = "journal")]
+        let journal = None;
+        Self {
+            name: None,
+            stack_size: None,
+            capacity: None,
+            initial: F::State::default(),
+            clock: Arc::new(SystemClock),
+            recorder: None,
+            supervisor: None,
+            on_drop: DropPolicy::default(),
+            fsm: None,
+            factory: None,
+            resume: false,
+            #[cfg(feature = "journal")]
+            journal,
+        }
+    }
+
+    /// Name machine. Name is given to its thread and is used in logs, traces,
+    /// panic message returned by [`Reactor::join`] and [`PanicReport`]s of
+    /// [`Supervisor`]. Errors of senders do not carry it.
+    ///
+    /// [`PanicReport`]: crate::PanicReport
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Stack size of machine thread, in bytes
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// Bound event queue to `capacity` events, see [`Reactor::bounded`].
+    /// Panics if `capacity` is 0.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "queue capacity must be at least 1");
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Start with already built `fsm` instead of a new one. Restarts by
+    /// [`Supervisor`] still build new machines, see `factory`.
+    pub fn fsm(mut self, fsm: F) -> Self
+    where
+        F: Send,
+    {
+        self.fsm = Some(Box::new(move || fsm));
+        self
+    }
+
+    /// Build new machines with `factory` instead of `F::new()`, e.g. to hand
+    /// them configuration or handles. Used at start, unless given `fsm`, and
+    /// on every restart by [`Supervisor`].
+    ///
+    /// # Example
+    /// ```
+    /// # use pakr_fsm::*;
+    /// #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    /// enum Ev {
+    ///     Tick,
+    ///     Boom,
+    /// }
+    ///
+    /// #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    /// struct Counting;
+    ///
+    /// struct Counter {
+    ///     limit: u32,
+    ///     count: u32,
+    /// }
+    ///
+    /// impl Counter {
+    ///     fn with_limit(limit: u32) -> Self {
+    ///         Self {
+    ///             limit,
+    ///             count: 0,
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// impl FSM for Counter {
+    ///     type Event = Ev;
+    ///     type Response = u32;
+    ///     type State = Counting;
+    ///
+    ///     fn new() -> Self { Self::with_limit(10) }
+    ///
+    ///     fn trasnsit(&mut self, _: &Counting, ev: &Ev) -> (Option<Counting>, Option<u32>) {
+    ///         match ev {
+    ///             Ev::Boom => panic!("boom"),
+    ///             Ev::Tick if self.count + 1 == self.limit => (None, Some(self.limit)),
+    ///             Ev::Tick => {
+    ///                 self.count += 1;
+    ///                 (Some(Counting), None)
+    ///             }
+    ///         }
+    ///     }
+    ///
+    ///     fn respond(&mut self, _: &Counting, _: &Option<Counting>, _: &u32) {}
+    /// }
+    ///
+    /// let fsm = ReactorBuilder::new()
+    ///     .factory(|| Counter::with_limit(2))
+    ///     .supervisor(Supervisor::new(Restart::Fresh))
+    ///     .spawn()
+    ///     .unwrap();
+    ///
+    /// fsm.send(Ev::Tick).unwrap();
+    /// fsm.send(Ev::Boom).unwrap();
+    /// fsm.send(Ev::Tick).unwrap();
+    /// assert_eq!(fsm.ask(Ev::Tick), Ok(Some(2)));
+    /// ```
+    pub fn factory(mut self, factory: impl FnMut() -> F + Send + 'static) -> Self {
+        self.factory = Some(Box::new(factory));
+        self
+    }
+
+    /// Start machine in `state` instead of the default one. It is also the
+    /// state [`Restart::Fresh`](crate::Restart::Fresh) restarts in.
+    pub fn initial_state(mut self, state: F::State) -> Self {
+        self.initial = state;
+        self
+    }
+
+    /// Measure time with `clock`, see [`Reactor::with_clock`]
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Record last `capacity` steps, see [`Reactor::with_history`]
+    pub fn history(mut self, capacity: usize) -> Self
+    where
+        F::Event: Clone,
+    {
+        self.recorder = Some(Recorder::new(capacity));
+        self
+    }
+
+    /// Supervise machine, see [`Supervisor`]
+    pub fn supervisor(mut self, supervisor: Supervisor<F>) -> Self {
+        self.supervisor = Some(supervisor);
+        self
+    }
+
+    /// Set what dropping `Reactor` does, see [`Reactor::drop_policy`]
+    pub fn drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.on_drop = policy;
+        self
+    }
+
+    /// Resume machine from `snapshot`, see [`Reactor::restore`]. State of
+    /// snapshot becomes the initial state.
+    #[cfg(feature = "serde")]
+    pub fn restore(mut self, snapshot: Snapshot<F>) -> Self
+    where
+        F: Send,
+    {
+        let fsm = snapshot.fsm;
+        self.fsm = Some(Box::new(move || fsm));
+        self.initial = snapshot.state;
+        self.resume = true;
+        self
+    }
+
+    /// Rebuild machine from `journal` and keep recording to it, see
+    /// [`Reactor::with_journal`]. Fails if journal could not be read.
+    ///
+    /// Machine starts from snapshot of the journal, if there is one,
+    /// otherwise from the initial state.
+    #[cfg(feature = "journal")]
+    pub fn journal(mut self, mut journal: impl Journal<F> + 'static) -> io::Result<Self>
+    where
+        F: Send,
+    {
+        let Recovered {
+            snapshot,
+            entries,
+        } = journal.load()?;
+        if let Some(snapshot) = snapshot {
+            self = self.restore(snapshot);
+        }
+        self.journal = Some(Journaling::new(Box::new(journal), entries));
+        Ok(self)
+    }
+
+    /// Spawn configured `Reactor`. Fails if thread could not be created.
+    pub fn spawn(self) -> io::Result<Reactor<F>> { Reactor::spawn(self) }
+}
+
+impl<F: FSM + 'static> Default for ReactorBuilder<F> {
+    fn default() -> Self { Self::new() }
+}
@@ -0,0 +1,135 @@
+//! Source of time for timers, timeouts and metrics of machines.
+
+use std::{
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+/// Callback registered with [`Clock::subscribe`]
+pub type ClockWaker = Weak<dyn Fn() + Send + Sync>;
+
+/// Time as seen by a machine.
+///
+/// All time-based features of [`Reactor`](crate::Reactor) go through its clock:
+/// timers scheduled with [`Context`](crate::Context), send timeouts, timeouts
+/// of [`StateWatch`](crate::StateWatch), restart window of
+/// [`Supervisor`](crate::Supervisor) and time spent in state. So do timers of
+/// [`AsyncReactor`](crate::AsyncReactor). Only
+/// [`PendingReply::wait_timeout`](crate::PendingReply::wait_timeout) waits in
+/// real time.
+pub trait Clock: Send + Sync {
+    /// Current time
+    fn now(&self) -> Instant;
+
+    /// Real time to block for while waiting for `deadline`. `None` means
+    /// blocking until woken by one of the subscribed callbacks.
+    fn wait_for(&self, deadline: Instant) -> Option<Duration> {
+        Some(deadline.saturating_duration_since(self.now()))
+    }
+
+    /// Register `wake`, to be called whenever clock moves by other means than
+    /// the real time passing, so blocked waiters can recheck their deadlines.
+    /// Callbacks that are gone may be discarded.
+    fn subscribe(&self, _wake: ClockWaker) {}
+}
+
+/// Real time of the operating system
+#[derive(Copy, Clone, Default, Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant { Instant::now() }
+}
+
+struct Manual {
+    now:    Instant,
+    wakers: Vec<ClockWaker>,
+}
+
+/// Virtual clock, that moves only when explicitly advanced.
+///
+/// Clones share the same time, so one clone can be handed to the machine and
+/// the other kept by the test.
+///
+/// # Example
+/// ```
+/// # use pakr_fsm::*;
+/// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// # enum MyEv { Arm, Fire }
+/// # #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+/// # enum MyState { #[default] Idle, Armed, Fired }
+/// # #[derive(Default)]
+/// # struct MyFSM { ctx: Option<Context<MyEv>> }
+/// # impl FSM for MyFSM {
+/// #     type Event = MyEv;
+/// #     type Response = ();
+/// #     type State = MyState;
+/// #     fn new() -> Self { Self::default() }
+/// #     fn trasnsit(&mut self, state: &MyState, ev: &MyEv) -> (Option<MyState>, Option<()>) {
+/// #         match (state, ev) {
+/// #             (MyState::Idle, MyEv::Arm) => {
+/// #                 self.ctx.as_ref().unwrap().schedule(Duration::from_secs(3600), MyEv::Fire);
+/// #                 (Some(MyState::Armed), None)
+/// #             }
+/// #             (MyState::Armed, MyEv::Fire) => (Some(MyState::Fired), None),
+/// #             (state, _) => (Some(*state), None),
+/// #         }
+/// #     }
+/// #     fn respond(&mut self, _: &MyState, _: &Option<MyState>, _: &()) {}
+/// #     fn attach(&mut self, ctx: Context<MyEv>) { self.ctx = Some(ctx) }
+/// # }
+/// use std::time::Duration;
+///
+/// let clock = ManualClock::new();
+/// let fsm = Reactor::<MyFSM>::with_clock(clock.clone());
+///
+/// // Hour-long timer fires at once, when clock is told so
+/// fsm.ask(MyEv::Arm).unwrap();
+/// clock.advance(Duration::from_secs(1800));
+/// assert_eq!(fsm.time_in_state(), Duration::from_secs(1800));
+/// let mut watch = fsm.watch();
+/// clock.advance(Duration::from_secs(1800));
+/// assert_eq!(watch.changed(), Ok(MyState::Fired));
+/// ```
+#[derive(Clone)]
+pub struct ManualClock {
+    inner: Arc<Mutex<Manual>>,
+}
+
+impl ManualClock {
+    /// Create clock showing current real time, that stays still from now on
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Manual {
+                now:    Instant::now(),
+                wakers: Vec::new(),
+            })),
+        }
+    }
+
+    /// Move clock forward by `by`, waking everything waiting on it.
+    pub fn advance(&self, by: Duration) {
+        let wakers: Vec<_> = {
+            let mut manual = self.inner.lock().unwrap();
+            manual.now += by;
+            manual.wakers.retain(|wake| wake.strong_count() > 0);
+            manual.wakers.iter().filter_map(Weak::upgrade).collect()
+        };
+        // Outside of the lock, as waiters read the clock holding their own locks
+        for wake in wakers {
+            wake();
+        }
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self { Self::new() }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant { self.inner.lock().unwrap().now }
+
+    fn wait_for(&self, _deadline: Instant) -> Option<Duration> { None }
+
+    fn subscribe(&self, wake: ClockWaker) { self.inner.lock().unwrap().wakers.push(wake) }
+}
@@ -0,0 +1,356 @@
+//! Services offered by the running machine to its `FSM`.
+
+use crate::Clock;
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Identifier of a timer armed with [`Context::schedule`]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TimerId(u64);
+
+struct Timer<E> {
+    id:       TimerId,
+    deadline: Instant,
+    event:    E,
+    /// Armed during the current step, not yet owned by any state
+    fresh:    bool,
+    /// Region of orthogonal machine that armed it, see [`Context::region`]
+    region:   Arc<[u64]>,
+}
+
+struct Inner<E> {
+    timers:      Vec<Timer<E>>,
+    next_id:     u64,
+    next_region: u64,
+    /// Event being handled is deferred
+    deferring:   bool,
+}
+
+/// Handle to the machine running an `FSM`, handed over by
+/// [`FSM::attach`](crate::FSM::attach).
+///
+/// Timers scheduled with it deliver their event to the machine once delay
+/// elapses. Timer belongs to the state machine is in after the step that armed
+/// it (so arming it in `trasnsit` of a transition to `B` makes it `B`'s
+/// timer), and is cancelled automatically when that state is exited or machine
+/// terminates.
+///
+/// Timers are serviced by [`Reactor`](crate::Reactor) and
+/// [`Driver`](crate::Driver); for [`Machine`](crate::Machine) see
+/// [`Machine::take_expired`](crate::Machine::take_expired).
+///
+/// # Example
+/// ```
+/// use pakr_fsm::*;
+/// use std::{sync::Arc, time::Duration};
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// enum Ev {
+///     Call,
+///     Answer,
+///     NoAnswer,
+/// }
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+/// enum St {
+///     #[default]
+///     Idle,
+///     Ringing,
+///     Missed,
+/// }
+///
+/// #[derive(Default)]
+/// struct Phone {
+///     ctx: Option<Context<Ev>>,
+/// }
+///
+/// impl FSM for Phone {
+///     type Event = Ev;
+///     type Response = ();
+///     type State = St;
+///
+///     fn new() -> Self { Self::default() }
+///
+///     fn trasnsit(&mut self, state: &St, ev: &Ev) -> (Option<St>, Option<()>) {
+///         match (state, ev) {
+///             (St::Idle, Ev::Call) => (Some(St::Ringing), None),
+///             (St::Ringing, Ev::Answer) => (Some(St::Idle), None),
+///             (St::Ringing, Ev::NoAnswer) => (Some(St::Missed), None),
+///             (state, _) => (Some(*state), None),
+///         }
+///     }
+///
+///     fn respond(&mut self, _: &St, _: &Option<St>, _: &()) {}
+///
+///     fn attach(&mut self, ctx: Context<Ev>) { self.ctx = Some(ctx) }
+///
+///     fn on_enter(&mut self, state: &St) {
+///         if *state == St::Ringing {
+///             let ctx = self.ctx.as_ref().unwrap();
+///             ctx.schedule(Duration::from_millis(50), Ev::NoAnswer);
+///         }
+///     }
+/// }
+///
+/// let clock = ManualClock::new();
+/// let mut phone = Machine::with_clock(Phone::new(), St::Idle, Arc::new(clock.clone()));
+///
+/// // Answered in time, timer of `Ringing` is gone with it
+/// phone.step(Ev::Call);
+/// phone.step(Ev::Answer);
+/// clock.advance(Duration::from_millis(100));
+/// assert_eq!(phone.take_expired(), None);
+///
+/// phone.step(Ev::Call);
+/// clock.advance(Duration::from_millis(30));
+/// assert_eq!(phone.take_expired(), None);
+/// clock.advance(Duration::from_millis(30));
+/// let missed = phone.take_expired().unwrap();
+/// phone.step(missed);
+/// assert_eq!(*phone.state(), St::Missed);
+/// ```
+pub struct Context<E> {
+    inner:  Arc<Mutex<Inner<E>>>,
+    clock:  Arc<dyn Clock>,
+    /// Regions this context belongs to, outermost first; empty for the
+    /// whole machine
+    region: Arc<[u64]>,
+}
+
+impl<E> Context<E> {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                timers:      Vec::new(),
+                next_id:     0,
+                next_region: 0,
+                deferring:   false,
+            })),
+            clock,
+            region: Arc::from([]),
+        }
+    }
+
+    /// Context of a new region of orthogonal machine, nested in the region of
+    /// this context. Timers armed through it belong to that region only.
+    pub(crate) fn region(&self) -> Self {
+        let mut inner = self.lock();
+        let tag = inner.next_region;
+        inner.next_region += 1;
+        Self {
+            inner:  self.inner.clone(),
+            clock:  self.clock.clone(),
+            region: self.region.iter().copied().chain([tag]).collect(),
+        }
+    }
+
+    /// Checks whether `timer` was armed in the region of this context
+    fn owns(&self, timer: &Timer<E>) -> bool { timer.region.starts_with(&self.region) }
+
+    /// Current time of the machine's [`Clock`]
+    pub fn now(&self) -> Instant { self.clock.now() }
+
+    fn lock(&self) -> MutexGuard<'_, Inner<E>> { self.inner.lock().unwrap() }
+
+    /// Deliver `ev` to the machine after `delay`, unless cancelled earlier.
+    pub fn schedule(&self, delay: Duration, ev: E) -> TimerId {
+        let mut inner = self.lock();
+        let id = TimerId(inner.next_id);
+        inner.next_id += 1;
+        inner.timers.push(Timer {
+            id,
+            deadline: self.clock.now() + delay,
+            event: ev,
+            fresh: true,
+            region: self.region.clone(),
+        });
+        id
+    }
+
+    /// Cancel timer. Returns `false` if it already fired or was cancelled.
+    pub fn cancel(&self, id: TimerId) -> bool {
+        let mut inner = self.lock();
+        let before = inner.timers.len();
+        inner.timers.retain(|timer| timer.id != id);
+        inner.timers.len() != before
+    }
+
+    /// Cancel all pending timers, only ones of its own region when called by
+    /// region of [`Orthogonal`](crate::Orthogonal) machine
+    pub fn cancel_all(&self) {
+        let mut inner = self.lock();
+        inner.timers.retain(|timer| !self.owns(timer));
+    }
+
+    /// Defer event being handled by `trasnsit`.
+    ///
+    /// Event is kept and the transition is ignored, so machine stays in its
+    /// state with no response. Deferred events are reconsidered, in order they
+    /// arrived, after the next state change. Calls outside of `trasnsit` have
+    /// no effect.
+    ///
+    /// Asking party of a deferred event is replied to once it is finally
+    /// handled, or gets `NoReply` if machine ends before.
+    ///
+    /// Regions of [`Orthogonal`](crate::Orthogonal) machine must not defer, as
+    /// other regions have already handled the event. Such calls are ignored,
+    /// and panic in debug builds.
+    ///
+    /// # Example
+    /// ```
+    /// use pakr_fsm::*;
+    ///
+    /// #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    /// enum Ev {
+    ///     Print(u32),
+    ///     Done,
+    /// }
+    ///
+    /// #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    /// enum St {
+    ///     #[default]
+    ///     Idle,
+    ///     Busy(u32),
+    /// }
+    ///
+    /// #[derive(Default)]
+    /// struct Printer {
+    ///     ctx: Option<Context<Ev>>,
+    /// }
+    ///
+    /// impl FSM for Printer {
+    ///     type Event = Ev;
+    ///     type Response = ();
+    ///     type State = St;
+    ///
+    ///     fn new() -> Self { Self::default() }
+    ///
+    ///     fn trasnsit(&mut self, state: &St, ev: &Ev) -> (Option<St>, Option<()>) {
+    ///         match (state, ev) {
+    ///             (St::Idle, Ev::Print(job)) => (Some(St::Busy(*job)), None),
+    ///             (St::Busy(_), Ev::Print(_)) => {
+    ///                 self.ctx.as_ref().unwrap().defer();
+    ///                 (Some(*state), None)
+    ///             }
+    ///             (_, Ev::Done) => (Some(St::Idle), None),
+    ///         }
+    ///     }
+    ///
+    ///     fn respond(&mut self, _: &St, _: &Option<St>, _: &()) {}
+    ///
+    ///     fn attach(&mut self, ctx: Context<Ev>) { self.ctx = Some(ctx) }
+    /// }
+    ///
+    /// let mut m = Machine::<Printer>::new();
+    /// m.step(Ev::Print(1));
+    /// m.step(Ev::Print(2));
+    /// m.step(Ev::Print(3));
+    /// assert_eq!(m.take_replayed(), None);
+    ///
+    /// m.step(Ev::Done);
+    /// let job = m.take_replayed().unwrap();
+    /// m.step(job);
+    /// assert_eq!(*m.state(), St::Busy(2));
+    /// let job = m.take_replayed().unwrap();
+    /// m.step(job);
+    /// assert_eq!(m.take_replayed(), None);
+    ///
+    /// m.step(Ev::Done);
+    /// assert_eq!(m.take_replayed(), Some(Ev::Print(3)));
+    ///
+    /// let printer = Reactor::<Printer>::new();
+    /// printer.send(Ev::Print(1)).unwrap();
+    /// let queued = printer.request(Ev::Print(2)).unwrap();
+    /// printer.send(Ev::Done).unwrap();
+    /// assert_eq!(queued.wait().unwrap().state, Some(St::Busy(2)));
+    /// ```
+    pub fn defer(&self) {
+        debug_assert!(
+            self.region.is_empty(),
+            "region of orthogonal machine deferred event"
+        );
+        if self.region.is_empty() {
+            self.lock().deferring = true;
+        }
+    }
+
+    /// Checks whether `defer` was called during current step
+    pub(crate) fn is_deferring(&self) -> bool { self.lock().deferring }
+
+    /// Hand timers armed during a step over to the resulting state, dropping
+    /// the ones of the old state if it was exited.
+    pub(crate) fn settle(&self, exited: bool) {
+        let mut inner = self.lock();
+        inner.deferring = false;
+        if exited {
+            inner.timers.retain(|timer| timer.fresh);
+        }
+        for timer in &mut inner.timers {
+            timer.fresh = false;
+        }
+    }
+
+    /// Keep timers of the region of this context when machine changes state,
+    /// as the region itself stays in its state
+    pub(crate) fn keep(&self) {
+        let mut inner = self.lock();
+        for timer in &mut inner.timers {
+            if self.owns(timer) {
+                timer.fresh = true;
+            }
+        }
+    }
+
+    /// Earliest deadline of pending timers
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.lock().timers.iter().map(|timer| timer.deadline).min()
+    }
+
+    /// Remove the earliest timer that is already due, returning its event.
+    pub(crate) fn take_expired(&self) -> Option<E> {
+        let now = self.clock.now();
+        let mut inner = self.lock();
+        let (index, _) = inner
+            .timers
+            .iter()
+            .enumerate()
+            .filter(|(_, timer)| timer.deadline <= now)
+            .min_by_key(|(_, timer)| timer.deadline)?;
+        Some(inner.timers.remove(index).event)
+    }
+
+    /// Remove the earliest timer of event `ev`, whether due or not. Returns
+    /// whether there was any.
+    #[cfg(feature = "journal")]
+    pub(crate) fn take_matching(&self, ev: &E) -> bool
+    where
+        E: PartialEq,
+    {
+        let mut inner = self.lock();
+        let found = inner
+            .timers
+            .iter()
+            .enumerate()
+            .filter(|(_, timer)| timer.event == *ev)
+            .min_by_key(|(_, timer)| timer.deadline);
+        match found {
+            Some((index, _)) => {
+                inner.timers.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<E> Clone for Context<E> {
+    fn clone(&self) -> Self {
+        Self {
+            inner:  self.inner.clone(),
+            clock:  self.clock.clone(),
+            region: self.region.clone(),
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Describing structure of a machine and rendering it to
+//! [Graphviz](https://graphviz.org) DOT.
+//!
+//! # Example
+//! ```
+//! use pakr_fsm::{dot::Enumerable, *};
+//!
+//! #[derive(Default)]
+//! struct Switch;
+//!
+//! fsm! {
+//!     pub enum Light {
+//!         #[default]
+//!         Off,
+//!         On,
+//!     }
+//!
+//!     pub enum Press {
+//!         Toggle,
+//!         Break,
+//!     }
+//!
+//!     impl FSM for Switch {
+//!         type Response = &'static str;
+//!
+//!         transitions {
+//!             Off + Toggle => On / "on";
+//!             On + Toggle => Off;
+//!             On + Break => ! / "broken";
+//!         }
+//!     }
+//! }
+//!
+//! assert_eq!(Light::variants(), [Light::Off, Light::On]);
+//! assert_eq!(dot::transitions::<Switch>().len(), 3);
+//! assert_eq!(
+//!     dot::render::<Switch>(),
+//!     r#"digraph fsm {
+//!     "__start" [shape=point];
+//!     "__end" [shape=doublecircle, label=""];
+//!     "Off";
+//!     "On";
+//!     "__start" -> "Off";
+//!     "Off" -> "On" [label="Toggle / \"on\""];
+//!     "On" -> "Off" [label="Toggle"];
+//!     "On" -> "__end" [label="Break / \"broken\""];
+//! }
+//! "#
+//! );
+//! ```
+
+use crate::{Context, SystemClock, FSM};
+use std::{
+    fmt::{Debug, Write},
+    sync::Arc,
+};
+
+/// Types with finite, listable set of values, like plain enums.
+///
+/// Implemented by [`fsm!`](crate::fsm) for generated state and event enums.
+pub trait Enumerable: Sized {
+    /// All values of the type, in declaration order
+    fn variants() -> Vec<Self>;
+}
+
+/// Machines able to list their transition relation.
+///
+/// Implemented by [`fsm!`](crate::fsm) from its transition table, including
+/// guarded rows. Hand-written machines can implement it with [`probe`].
+pub trait Transitions: FSM + Sized {
+    /// Transition relation of the machine
+    fn transitions() -> Vec<Edge<Self>>;
+}
+
+/// Single element of machine's transition relation
+pub struct Edge<F: FSM> {
+    /// State transition starts in
+    pub from:     F::State,
+    /// Event triggering transition
+    pub event:    F::Event,
+    /// Name of guard transition depends on, if any
+    pub guard:    Option<&'static str>,
+    /// State transition ends in, `None` for termination
+    pub to:       Option<F::State>,
+    /// Response of the transition, as written in transition table or
+    /// `Debug`-formatted
+    pub response: Option<String>,
+}
+
+/// Transition relation of `F`, see [`Transitions`]
+pub fn transitions<F: Transitions>() -> Vec<Edge<F>> { F::transitions() }
+
+/// Enumerate transition relation of `F` by probing `trasnsit` with every pair
+/// of state and event.
+///
+/// Fallback for machines without transition table, with limits:
+///
+/// - each probe runs on a fresh `F::new()`, with detached [`Context`], so
+///   guards see machine as freshly created and only one branch of a guard is
+///   found, with no guard name,
+/// - any action `trasnsit` takes is run for real, on that fresh machine,
+/// - pairs leaving machine in the same state with no response are considered
+///   unhandled and skipped.
+///
+/// # Example
+/// ```
+/// use pakr_fsm::{dot::*, *};
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+/// enum Light {
+///     #[default]
+///     Off,
+///     On,
+/// }
+///
+/// impl Enumerable for Light {
+///     fn variants() -> Vec<Self> { vec![Light::Off, Light::On] }
+/// }
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// struct Toggle;
+///
+/// impl Enumerable for Toggle {
+///     fn variants() -> Vec<Self> { vec![Toggle] }
+/// }
+///
+/// struct Switch;
+///
+/// impl FSM for Switch {
+///     type Event = Toggle;
+///     type Response = ();
+///     type State = Light;
+///
+///     fn new() -> Self { Self }
+///
+///     fn trasnsit(&mut self, state: &Light, _: &Toggle) -> (Option<Light>, Option<()>) {
+///         match state {
+///             Light::Off => (Some(Light::On), None),
+///             Light::On => (Some(Light::Off), None),
+///         }
+///     }
+///
+///     fn respond(&mut self, _: &Light, _: &Option<Light>, _: &()) {}
+/// }
+///
+/// impl Transitions for Switch {
+///     fn transitions() -> Vec<Edge<Self>> { probe() }
+/// }
+///
+/// assert!(render::<Switch>().contains(r#""On" -> "Off" [label="Toggle"];"#));
+/// ```
+pub fn probe<F>() -> Vec<Edge<F>>
+where
+    F: FSM,
+    F::State: Enumerable,
+    F::Event: Enumerable,
+    F::Response: Debug,
+{
+    let mut edges = Vec::new();
+    for from in F::State::variants() {
+        for event in F::Event::variants() {
+            let mut fsm = F::new();
+            fsm.attach(Context::new(Arc::new(SystemClock)));
+            let (to, response) = fsm.trasnsit(&from, &event);
+            if to.as_ref() != Some(&from) || response.is_some() {
+                edges.push(Edge {
+                    from: from.clone(),
+                    event,
+                    guard: None,
+                    to,
+                    response: response.map(|response| format!("{:?}", response)),
+                });
+            }
+        }
+    }
+    edges
+}
+
+/// Render transition relation of `F` as Graphviz DOT digraph.
+///
+/// Initial state is pointed to by `__start` node, terminating transitions
+/// lead to `__end` node. Edges are labelled `event [guard] / response`.
+pub fn render<F>() -> String
+where
+    F: Transitions,
+    F::State: Enumerable + Debug,
+    F::Event: Debug,
+{
+    let edges = transitions::<F>();
+    let mut out = String::from("digraph fsm {\n    \"__start\" [shape=point];\n");
+    if edges.iter().any(|edge| edge.to.is_none()) {
+        out.push_str("    \"__end\" [shape=doublecircle, label=\"\"];\n");
+    }
+    for state in F::State::variants() {
+        writeln!(out, "    {};", node(&state)).unwrap();
+    }
+    writeln!(out, "    \"__start\" -> {};", node(&F::State::default())).unwrap();
+
+    for edge in edges {
+        let to = edge
+            .to
+            .as_ref()
+            .map_or_else(|| "\"__end\"".to_string(), node);
+        let mut label = format!("{:?}", edge.event);
+        if let Some(guard) = edge.guard {
+            write!(label, " [{}]", guard).unwrap();
+        }
+        if let Some(response) = &edge.response {
+            write!(label, " / {}", response).unwrap();
+        }
+        writeln!(
+            out,
+            "    {} -> {} [label={}];",
+            node(&edge.from),
+            to,
+            quote(&label)
+        )
+        .unwrap();
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn node<T: Debug>(value: &T) -> String { quote(&format!("{:?}", value)) }
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,81 @@
+//! Errors of [`Reactor`](crate::Reactor) operations.
+
+use std::{any::Any, error, fmt};
+
+/// Error of an operation on a running machine.
+///
+/// When an event could not be delivered, it is handed back.
+#[derive(PartialEq, Eq, Clone)]
+pub enum ReactorError<E> {
+    /// Machine is no longer running
+    MachineTerminated(E),
+    /// Bounded queue is full
+    QueueFull(E),
+    /// Queue stayed full, or awaited change did not come, for the whole
+    /// timeout
+    Timeout(E),
+    /// Machine accepted the event, but ended before replying to it
+    NoReply,
+    /// Machine panicked, with given message
+    MachinePanicked(String),
+    /// Machine was dropped before it completed
+    Cancelled,
+}
+
+impl<E> ReactorError<E> {
+    /// Convert event carried by error
+    pub(crate) fn map<T>(self, f: impl FnOnce(E) -> T) -> ReactorError<T> {
+        match self {
+            Self::MachineTerminated(ev) => ReactorError::MachineTerminated(f(ev)),
+            Self::QueueFull(ev) => ReactorError::QueueFull(f(ev)),
+            Self::Timeout(ev) => ReactorError::Timeout(f(ev)),
+            Self::NoReply => ReactorError::NoReply,
+            Self::MachinePanicked(msg) => ReactorError::MachinePanicked(msg),
+            Self::Cancelled => ReactorError::Cancelled,
+        }
+    }
+
+    /// Build `MachinePanicked` out of panic payload
+    pub(crate) fn panicked(payload: &(dyn Any + Send)) -> Self {
+        Self::MachinePanicked(panic_message(payload))
+    }
+}
+
+/// Message carried by panic payload
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+impl<E> fmt::Debug for ReactorError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MachineTerminated(..) => f.write_str("MachineTerminated(..)"),
+            Self::QueueFull(..) => f.write_str("QueueFull(..)"),
+            Self::Timeout(..) => f.write_str("Timeout(..)"),
+            Self::NoReply => f.write_str("NoReply"),
+            Self::MachinePanicked(msg) => f.debug_tuple("MachinePanicked").field(msg).finish(),
+            Self::Cancelled => f.write_str("Cancelled"),
+        }
+    }
+}
+
+impl<E> fmt::Display for ReactorError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MachineTerminated(..) => f.write_str("machine is no longer running"),
+            Self::QueueFull(..) => f.write_str("event queue is full"),
+            Self::Timeout(..) => f.write_str("operation timed out"),
+            Self::NoReply => f.write_str("machine ended without replying"),
+            Self::MachinePanicked(msg) => write!(f, "machine panicked: {}", msg),
+            Self::Cancelled => f.write_str("machine was dropped before completion"),
+        }
+    }
+}
+
+impl<E> error::Error for ReactorError<E> {}
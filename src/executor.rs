@@ -0,0 +1,137 @@
+//! Tiny single-threaded executor, good enough to drive
+//! [`AsyncReactor`](crate::AsyncReactor) in tests and examples.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+type LocalTask = Pin<Box<dyn Future<Output = ()>>>;
+
+/// Index of the future passed to `run_until`
+const MAIN: usize = usize::MAX;
+
+/// Queue of tasks that were woken and need polling
+struct ReadyQueue {
+    ready:  Mutex<VecDeque<usize>>,
+    thread: Thread,
+}
+
+struct TaskWaker {
+    id:    usize,
+    queue: Arc<ReadyQueue>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) { self.wake_by_ref() }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.queue.ready.lock().unwrap().push_back(self.id);
+        self.queue.thread.unpark();
+    }
+}
+
+/// Pool of futures run on the current thread.
+pub struct LocalPool {
+    tasks: Vec<Option<LocalTask>>,
+    queue: Arc<ReadyQueue>,
+}
+
+impl LocalPool {
+    /// Create empty pool bound to the current thread.
+    pub fn new() -> Self {
+        Self {
+            tasks: Vec::new(),
+            queue: Arc::new(ReadyQueue {
+                ready:  Mutex::new(VecDeque::new()),
+                thread: thread::current(),
+            }),
+        }
+    }
+
+    /// Add a future to the pool. It is not polled until one of `run` methods
+    /// is called.
+    pub fn spawn<Fut: Future<Output = ()> + 'static>(&mut self, fut: Fut) {
+        let id = self.tasks.len();
+        self.tasks.push(Some(Box::pin(fut)));
+        self.queue.ready.lock().unwrap().push_back(id);
+    }
+
+    /// Run all spawned futures to completion.
+    pub fn run(&mut self) {
+        while self.tasks.iter().any(Option::is_some) {
+            self.poll_ready();
+            if self.tasks.iter().any(Option::is_some) {
+                self.park();
+            }
+        }
+    }
+
+    /// Run spawned futures until `fut` completes, returning its output.
+    ///
+    /// Futures still pending at that point stay in the pool.
+    pub fn run_until<Fut: Future>(&mut self, fut: Fut) -> Fut::Output {
+        let mut fut = Box::pin(fut);
+        let waker = self.waker(MAIN);
+        self.queue.ready.lock().unwrap().push_back(MAIN);
+
+        loop {
+            if self.poll_ready() {
+                if let Poll::Ready(out) = fut.as_mut().poll(&mut Context::from_waker(&waker)) {
+                    return out;
+                }
+            }
+            self.park();
+        }
+    }
+
+    /// Poll every woken task; returns `true` if `MAIN` was woken.
+    fn poll_ready(&mut self) -> bool {
+        let mut main = false;
+        loop {
+            let id = match self.queue.ready.lock().unwrap().pop_front() {
+                Some(id) => id,
+                None => return main,
+            };
+            if id == MAIN {
+                main = true;
+                continue;
+            }
+
+            let waker = self.waker(id);
+            if let Some(task) = &mut self.tasks[id] {
+                if task
+                    .as_mut()
+                    .poll(&mut Context::from_waker(&waker))
+                    .is_ready()
+                {
+                    self.tasks[id] = None;
+                }
+            }
+        }
+    }
+
+    fn park(&self) {
+        if self.queue.ready.lock().unwrap().is_empty() {
+            thread::park();
+        }
+    }
+
+    fn waker(&self, id: usize) -> Waker {
+        Waker::from(Arc::new(TaskWaker {
+            id,
+            queue: self.queue.clone(),
+        }))
+    }
+}
+
+impl Default for LocalPool {
+    fn default() -> Self { Self::new() }
+}
+
+/// Run `fut` to completion on the current thread.
+pub fn block_on<Fut: Future>(fut: Fut) -> Fut::Output { LocalPool::new().run_until(fut) }
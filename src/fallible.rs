@@ -0,0 +1,263 @@
+//! Transitions that may fail with a domain error.
+
+use crate::{Context, MaybeDebug, Priority, FSM};
+
+/// What machine does after a failed transition
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ErrorPolicy<S> {
+    /// Stay in the old state
+    Stay,
+    /// Terminate machine, with error as the last response
+    Terminate,
+    /// Move to given (error) state
+    Goto(S),
+}
+
+/// Trait `TryFSM` is [`FSM`] with transition returning `Result`.
+///
+/// Wrap implementation in [`Fallible`] to get regular `FSM`. Its response is
+/// `Result` of the inner response and error, so error of a transition is
+/// reported to `on_error`, then delivered like any other response, e.g. to
+/// [`Reactor::ask`](crate::Reactor::ask) or as the result of
+/// [`Reactor::join`](crate::Reactor::join).
+///
+/// # Example
+/// ```
+/// use pakr_fsm::*;
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// enum Ev {
+///     Add(u8),
+///     Reset,
+/// }
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+/// enum St {
+///     #[default]
+///     Counting,
+///     Broken,
+/// }
+///
+/// #[derive(Default)]
+/// struct Counter {
+///     total:  u8,
+///     errors: usize,
+/// }
+///
+/// impl TryFSM for Counter {
+///     type Error = &'static str;
+///     type Event = Ev;
+///     type Response = u8;
+///     type State = St;
+///
+///     fn new() -> Self { Self::default() }
+///
+///     fn try_trasnsit(
+///         &mut self,
+///         state: &St,
+///         ev: &Ev,
+///     ) -> Result<(Option<St>, Option<u8>), &'static str> {
+///         match (state, ev) {
+///             (St::Counting, Ev::Add(n)) => {
+///                 self.total = self.total.checked_add(*n).ok_or("overflow")?;
+///                 Ok((Some(St::Counting), Some(self.total)))
+///             }
+///             (St::Broken, Ev::Add(_)) => Err("broken"),
+///             (_, Ev::Reset) => {
+///                 self.total = 0;
+///                 Ok((Some(St::Counting), None))
+///             }
+///         }
+///     }
+///
+///     fn respond(&mut self, _: &St, _: &Option<St>, _: &u8) {}
+///
+///     fn error_policy(&self, state: &St, _: &&'static str) -> ErrorPolicy<St> {
+///         match state {
+///             St::Counting => ErrorPolicy::Goto(St::Broken),
+///             St::Broken => ErrorPolicy::Terminate,
+///         }
+///     }
+///
+///     fn on_error(&mut self, _: &St, _: &Ev, _: &&'static str) { self.errors += 1 }
+/// }
+///
+/// let mut m = Machine::<Fallible<Counter>>::new();
+/// m.step(Ev::Add(200));
+/// assert!(matches!(
+///     m.step(Ev::Add(100)),
+///     StepOutcome::Running {
+///         state:    St::Broken,
+///         response: Some(Err("overflow")),
+///     }
+/// ));
+/// assert!(matches!(
+///     m.step(Ev::Add(1)),
+///     StepOutcome::Terminated(Some(Err("broken")))
+/// ));
+/// assert_eq!(m.fsm().inner().errors, 2);
+/// ```
+pub trait TryFSM {
+    /// Events are sent from outer world to influence state of machine
+    type Event: Send + Eq + PartialEq + MaybeDebug + 'static;
+
+    /// Response are optional outcomes of transition
+    type Response: Send + 'static;
+
+    /// Current state of the machine. Default should init machine state to entry
+    /// one
+    type State: Eq + PartialEq + Default + Clone + Send + MaybeDebug + 'static;
+
+    /// Error of a failed transition
+    type Error: Send + 'static;
+
+    /// Creating a new machine
+    fn new() -> Self;
+
+    /// Mapping current state & event into a eventual new state and eventual
+    /// response, just like [`FSM::trasnsit`], or into an error.
+    #[allow(clippy::type_complexity)]
+    fn try_trasnsit(
+        &mut self,
+        old_state: &<Self as TryFSM>::State,
+        ev: &<Self as TryFSM>::Event,
+    ) -> Result<
+        (
+            Option<<Self as TryFSM>::State>,
+            Option<<Self as TryFSM>::Response>,
+        ),
+        <Self as TryFSM>::Error,
+    >;
+
+    /// Handling response of successful transition, see [`FSM::respond`]
+    fn respond(
+        &mut self,
+        old_state: &<Self as TryFSM>::State,
+        new_state: &Option<<Self as TryFSM>::State>,
+        resp: &<Self as TryFSM>::Response,
+    );
+
+    /// Deciding what to do after `try_trasnsit` failed in `state`. Default is
+    /// to stay in the old state.
+    fn error_policy(
+        &self,
+        _state: &<Self as TryFSM>::State,
+        _err: &<Self as TryFSM>::Error,
+    ) -> ErrorPolicy<<Self as TryFSM>::State> {
+        ErrorPolicy::Stay
+    }
+
+    /// Handling error of transition, called before `error_policy`
+    fn on_error(
+        &mut self,
+        _state: &<Self as TryFSM>::State,
+        _ev: &<Self as TryFSM>::Event,
+        _err: &<Self as TryFSM>::Error,
+    ) {
+    }
+
+    /// Final words of machine stopped from the outside, see
+    /// [`FSM::on_shutdown`]
+    fn on_shutdown(
+        &mut self,
+        _state: &<Self as TryFSM>::State,
+    ) -> Option<<Self as TryFSM>::Response> {
+        None
+    }
+
+    /// Priority of `ev` in the event queue, see [`FSM::priority`]
+    fn priority(_ev: &<Self as TryFSM>::Event) -> Priority { Priority::Normal }
+
+    /// Receive [`Context`] of the running machine, see [`FSM::attach`]
+    fn attach(&mut self, _ctx: Context<<Self as TryFSM>::Event>) {}
+
+    /// Entry action of `state`, see [`FSM::on_enter`]
+    fn on_enter(&mut self, _state: &<Self as TryFSM>::State) {}
+
+    /// Exit action of `state`, see [`FSM::on_exit`]
+    fn on_exit(&mut self, _state: &<Self as TryFSM>::State) {}
+}
+
+/// Adapter running [`TryFSM`] machine as a regular `FSM`.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct Fallible<T> {
+    inner: T,
+}
+
+impl<T: TryFSM> Fallible<T> {
+    /// Wrap already built `inner` machine, e.g. for [`Reactor::from_parts`]
+    ///
+    /// [`Reactor::from_parts`]: crate::Reactor::from_parts
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+        }
+    }
+
+    /// Shared access to the wrapped machine
+    pub fn inner(&self) -> &T { &self.inner }
+
+    /// Exclusive access to the wrapped machine
+    pub fn inner_mut(&mut self) -> &mut T { &mut self.inner }
+}
+
+impl<T: TryFSM> FSM for Fallible<T> {
+    type Event = T::Event;
+    type Response = Result<T::Response, T::Error>;
+    type State = T::State;
+
+    fn new() -> Self {
+        Self {
+            inner: T::new()
+        }
+    }
+
+    fn trasnsit(
+        &mut self,
+        old_state: &<Self as FSM>::State,
+        ev: &<Self as FSM>::Event,
+    ) -> (
+        Option<<Self as FSM>::State>,
+        Option<<Self as FSM>::Response>,
+    ) {
+        match self.inner.try_trasnsit(old_state, ev) {
+            Ok((new_state, response)) => (new_state, response.map(Ok)),
+            Err(err) => {
+                self.inner.on_error(old_state, ev, &err);
+                let new_state = match self.inner.error_policy(old_state, &err) {
+                    ErrorPolicy::Stay => Some(old_state.clone()),
+                    ErrorPolicy::Terminate => None,
+                    ErrorPolicy::Goto(state) => Some(state),
+                };
+                (new_state, Some(Err(err)))
+            }
+        }
+    }
+
+    fn respond(
+        &mut self,
+        old_state: &<Self as FSM>::State,
+        new_state: &Option<<Self as FSM>::State>,
+        resp: &<Self as FSM>::Response,
+    ) {
+        if let Ok(resp) = resp {
+            self.inner.respond(old_state, new_state, resp)
+        }
+    }
+
+    fn on_shutdown(&mut self, state: &<Self as FSM>::State) -> Option<<Self as FSM>::Response> {
+        self.inner.on_shutdown(state).map(Ok)
+    }
+
+    fn priority(ev: &<Self as FSM>::Event) -> Priority { T::priority(ev) }
+
+    fn attach(&mut self, ctx: Context<<Self as FSM>::Event>) { self.inner.attach(ctx) }
+
+    fn on_enter(&mut self, state: &<Self as FSM>::State) { self.inner.on_enter(state) }
+
+    fn on_exit(&mut self, state: &<Self as FSM>::State) { self.inner.on_exit(state) }
+}
@@ -0,0 +1,561 @@
+//! Hierarchical (nested) states on top of flat [`FSM`].
+
+use crate::{Context, MaybeDebug, Priority, FSM};
+use std::mem;
+
+/// Trait `Hierarchical` describes machine, which states form a tree.
+///
+/// Event not handled by a state bubbles up to its parent. Transition exits
+/// states up to the least common ancestor of handling and target states and
+/// enters states down to the target. Wrap implementation in [`Hierarchy`] to
+/// get regular `FSM`, e.g. to run it on [`Reactor`](crate::Reactor).
+///
+/// Exit actions of a transition run before its `respond` and entry actions
+/// after it, just like [`FSM::on_exit`] and [`FSM::on_enter`]. Transition
+/// ending in the same innermost state runs neither, as it does not change
+/// state of the machine. When machine starts, the initial state, which must be
+/// a leaf (machine panics otherwise), is entered together with its ancestors.
+/// When it terminates or is stopped from the outside, active state and its
+/// ancestors are exited.
+///
+/// # Example
+/// ```
+/// use pakr_fsm::*;
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// enum Ev {
+///     Connect,
+///     Work,
+///     Done,
+///     Drop,
+/// }
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// enum St {
+///     Disconnected,
+///     Connected,
+///     Idle,
+///     Busy,
+/// }
+///
+/// impl Default for St {
+///     fn default() -> Self { Self::Disconnected }
+/// }
+///
+/// #[derive(Default)]
+/// struct Proto {
+///     log: Vec<String>,
+/// }
+///
+/// impl Hierarchical for Proto {
+///     type Event = Ev;
+///     type Response = ();
+///     type State = St;
+///
+///     fn new() -> Self { Self::default() }
+///
+///     fn parent(state: &St) -> Option<St> {
+///         match state {
+///             St::Idle | St::Busy => Some(St::Connected),
+///             _ => None,
+///         }
+///     }
+///
+///     fn initial(state: &St) -> Option<St> {
+///         match state {
+///             St::Connected => Some(St::Idle),
+///             _ => None,
+///         }
+///     }
+///
+///     fn handle(&mut self, state: &St, ev: &Ev) -> Option<(Option<St>, Option<()>)> {
+///         match (state, ev) {
+///             (St::Disconnected, Ev::Connect) => Some((Some(St::Connected), None)),
+///             (St::Idle, Ev::Work) => Some((Some(St::Busy), Some(()))),
+///             (St::Busy, Ev::Done) => Some((Some(St::Idle), None)),
+///             // Common to both `Idle` and `Busy`
+///             (St::Connected, Ev::Drop) => Some((Some(St::Disconnected), None)),
+///             _ => None,
+///         }
+///     }
+///
+///     fn enter(&mut self, state: &St) { self.log.push(format!("+{:?}", state)) }
+///
+///     fn exit(&mut self, state: &St) { self.log.push(format!("-{:?}", state)) }
+///
+///     fn respond(&mut self, _: &St, _: &Option<St>, _: &()) { self.log.push("!".to_string()) }
+/// }
+///
+/// let mut m = Machine::<Hierarchy<Proto>>::new();
+/// m.step(Ev::Connect);
+/// m.step(Ev::Work);
+/// assert_eq!(*m.state(), St::Busy);
+/// m.step(Ev::Drop);
+/// assert_eq!(*m.state(), St::Disconnected);
+/// m.step(Ev::Connect);
+/// m.shutdown();
+/// assert_eq!(
+///     m.fsm().inner().log,
+///     [
+///         "+Disconnected",
+///         "-Disconnected",
+///         "+Connected",
+///         "+Idle",
+///         "-Idle",
+///         "!",
+///         "+Busy",
+///         "-Busy",
+///         "-Connected",
+///         "+Disconnected",
+///         "-Disconnected",
+///         "+Connected",
+///         "+Idle",
+///         "-Idle",
+///         "-Connected"
+///     ]
+/// );
+/// ```
+pub trait Hierarchical {
+    /// Events are sent from outer world to influence state of machine
+    type Event: Send + Eq + PartialEq + MaybeDebug + 'static;
+
+    /// Response are optional outcomes of transition
+    type Response: Send + 'static;
+
+    /// Any state of the tree, both composite and leaf one. Default should init
+    /// machine state to entry one, which must be a leaf, as machine starting
+    /// in composite state would not descend to its `initial` substate. Starting
+    /// in composite state panics.
+    type State: Eq + PartialEq + Default + Clone + Send + MaybeDebug + 'static;
+
+    /// Creating a new machine
+    fn new() -> Self;
+
+    /// Parent of `state`, `None` for top-level states
+    fn parent(state: &<Self as Hierarchical>::State) -> Option<<Self as Hierarchical>::State>;
+
+    /// Substate entered when transition targets composite `state`. `None` (the
+    /// default) means `state` is a leaf.
+    fn initial(_state: &<Self as Hierarchical>::State) -> Option<<Self as Hierarchical>::State> {
+        None
+    }
+
+    /// History of composite `state`, deciding which substate is entered when
+    /// transition targets it. Default is `History::None`, so `initial` is.
+    fn history(_state: &<Self as Hierarchical>::State) -> History { History::None }
+
+    /// Mapping state & event into a eventual new state and eventual response,
+    /// just like [`FSM::trasnsit`].
+    ///
+    /// It is called first for the current state, then for its ancestors, until
+    /// some returns `Some`. Event not handled by any state is ignored.
+    #[allow(clippy::type_complexity)]
+    fn handle(
+        &mut self,
+        state: &<Self as Hierarchical>::State,
+        ev: &<Self as Hierarchical>::Event,
+    ) -> Option<(
+        Option<<Self as Hierarchical>::State>,
+        Option<<Self as Hierarchical>::Response>,
+    )>;
+
+    /// Final words of machine stopped from the outside, in innermost `state`,
+    /// see [`FSM::on_shutdown`]. Exit actions of active states follow.
+    fn shutdown(
+        &mut self,
+        _state: &<Self as Hierarchical>::State,
+    ) -> Option<<Self as Hierarchical>::Response> {
+        None
+    }
+
+    /// Priority of `ev` in the event queue, see [`FSM::priority`]
+    fn priority(_ev: &<Self as Hierarchical>::Event) -> Priority { Priority::Normal }
+
+    /// Receive [`Context`] of the running machine, see [`FSM::attach`]. Its
+    /// timers belong to the innermost active state.
+    fn attach(&mut self, _ctx: Context<<Self as Hierarchical>::Event>) {}
+
+    /// Entry action of `state`
+    fn enter(&mut self, _state: &<Self as Hierarchical>::State) {}
+
+    /// Exit action of `state`
+    fn exit(&mut self, _state: &<Self as Hierarchical>::State) {}
+
+    /// Handling response of transition, see [`FSM::respond`]
+    fn respond(
+        &mut self,
+        old_state: &<Self as Hierarchical>::State,
+        new_state: &Option<<Self as Hierarchical>::State>,
+        resp: &<Self as Hierarchical>::Response,
+    );
+}
+
+/// What composite state remembers of its substates, as given by
+/// [`Hierarchical::history`].
+///
+/// Memory is taken when composite state is exited and is kept by
+/// [`Hierarchy`], so it is a part of any snapshot of the machine. Composite
+/// state entered for the first time uses `initial` substate.
+///
+/// # Example
+/// ```
+/// use pakr_fsm::*;
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// enum Ev {
+///     Power,
+///     Band,
+///     Source,
+/// }
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+/// enum St {
+///     #[default]
+///     Off,
+///     On,
+///     Radio,
+///     Fm,
+///     Am,
+///     Cd,
+/// }
+///
+/// struct Player;
+///
+/// impl Hierarchical for Player {
+///     type Event = Ev;
+///     type Response = ();
+///     type State = St;
+///
+///     fn new() -> Self { Self }
+///
+///     fn parent(state: &St) -> Option<St> {
+///         match state {
+///             St::Radio | St::Cd => Some(St::On),
+///             St::Fm | St::Am => Some(St::Radio),
+///             _ => None,
+///         }
+///     }
+///
+///     fn initial(state: &St) -> Option<St> {
+///         match state {
+///             St::On => Some(St::Radio),
+///             St::Radio => Some(St::Fm),
+///             _ => None,
+///         }
+///     }
+///
+///     fn history(state: &St) -> History {
+///         match state {
+///             St::On => History::Deep,
+///             _ => History::None,
+///         }
+///     }
+///
+///     fn handle(&mut self, state: &St, ev: &Ev) -> Option<(Option<St>, Option<()>)> {
+///         match (state, ev) {
+///             (St::Off, Ev::Power) => Some((Some(St::On), None)),
+///             (St::On, Ev::Power) => Some((Some(St::Off), None)),
+///             (St::Fm, Ev::Band) => Some((Some(St::Am), None)),
+///             (St::Am, Ev::Band) => Some((Some(St::Fm), None)),
+///             (St::Radio, Ev::Source) => Some((Some(St::Cd), None)),
+///             (St::Cd, Ev::Source) => Some((Some(St::Radio), None)),
+///             _ => None,
+///         }
+///     }
+///
+///     fn respond(&mut self, _: &St, _: &Option<St>, _: &()) {}
+/// }
+///
+/// let mut m = Machine::<Hierarchy<Player>>::new();
+/// m.step(Ev::Power);
+/// assert_eq!(*m.state(), St::Fm);
+/// m.step(Ev::Band);
+/// m.step(Ev::Power);
+/// assert_eq!(*m.state(), St::Off);
+///
+/// // `On` resumes where it was, all the way down
+/// m.step(Ev::Power);
+/// assert_eq!(*m.state(), St::Am);
+///
+/// // `Radio` has no history of its own, so only `On` remembers the band
+/// m.step(Ev::Source);
+/// m.step(Ev::Source);
+/// assert_eq!(*m.state(), St::Fm);
+/// ```
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub enum History {
+    /// Always enter `initial` substate
+    #[default]
+    None,
+    /// Enter substate that was active last, then its `initial` substate (or
+    /// the one given by its own history)
+    Shallow,
+    /// Enter innermost state that was active last, with all states on the way
+    Deep,
+}
+
+/// Exit and entry actions of a transition, run by `on_exit` and `on_enter`
+struct Plan<S> {
+    /// States to exit, innermost first
+    exit:       Vec<S>,
+    /// Memory taken on exit, by composite state
+    remembered: Vec<(S, S)>,
+    /// States to enter, outermost first
+    enter:      Vec<S>,
+}
+
+/// Adapter running [`Hierarchical`] machine as a flat `FSM`.
+///
+/// State of the adapter is the innermost active state.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "H: serde::Serialize, H::State: serde::Serialize",
+        deserialize = "H: serde::Deserialize<'de>, H::State: serde::Deserialize<'de>"
+    ))
+)]
+pub struct Hierarchy<H: Hierarchical> {
+    inner:   H,
+    started: bool,
+    /// Actions of the transition in progress
+    #[cfg_attr(feature = "serde", serde(skip))]
+    plan:    Option<Plan<H::State>>,
+    /// Innermost state active when composite state with history was exited
+    /// last, by composite state
+    memory:  Vec<(H::State, H::State)>,
+}
+
+impl<H: Hierarchical> Hierarchy<H> {
+    /// Wrap already built `inner` machine, e.g. for [`Reactor::from_parts`]
+    ///
+    /// [`Reactor::from_parts`]: crate::Reactor::from_parts
+    pub fn new(inner: H) -> Self {
+        Self {
+            inner,
+            started: false,
+            plan: None,
+            memory: Vec::new(),
+        }
+    }
+
+    /// Shared access to the wrapped machine
+    pub fn inner(&self) -> &H { &self.inner }
+
+    /// Exclusive access to the wrapped machine
+    pub fn inner_mut(&mut self) -> &mut H { &mut self.inner }
+
+    /// `state` followed by all its ancestors
+    fn path(state: Option<H::State>) -> Vec<H::State> {
+        let mut path = Vec::new();
+        let mut cur = state;
+        while let Some(state) = cur {
+            cur = H::parent(&state);
+            path.push(state);
+        }
+        path
+    }
+
+    /// Innermost state last active below composite `state`, looking at memory
+    /// just `taken` first
+    fn recall(&self, taken: &[(H::State, H::State)], state: &H::State) -> Option<H::State> {
+        taken
+            .iter()
+            .chain(&self.memory)
+            .find(|(composite, _)| composite == state)
+            .map(|(_, leaf)| leaf.clone())
+    }
+
+    /// Remember `leaf` as the innermost state active below `state`
+    fn remember(&mut self, state: H::State, leaf: H::State) {
+        match self
+            .memory
+            .iter_mut()
+            .find(|(composite, _)| *composite == state)
+        {
+            Some((_, last)) => *last = leaf,
+            None => self.memory.push((state, leaf)),
+        }
+    }
+
+    /// Direct child of `ancestor` on the way down to `state`
+    fn child_towards(ancestor: &H::State, state: &H::State) -> Option<H::State> {
+        Self::path(Some(state.clone()))
+            .into_iter()
+            .find(|state| H::parent(state).as_ref() == Some(ancestor))
+    }
+
+    /// Substates of just entered `state` to enter as well, as given by their
+    /// history or `initial`, are added to `plan`. Returns new innermost state.
+    fn descend(&self, plan: &mut Plan<H::State>, state: H::State) -> H::State {
+        let mut cur = state;
+        // State being restored by history, down from `cur`
+        let mut recalled: Option<H::State> = None;
+        loop {
+            if recalled.as_ref() == Some(&cur) {
+                recalled = None;
+            }
+            if recalled.is_none() {
+                recalled = match H::history(&cur) {
+                    History::None => None,
+                    History::Shallow => {
+                        self.recall(&plan.remembered, &cur)
+                            .and_then(|leaf| Self::child_towards(&cur, &leaf))
+                    }
+                    History::Deep => self.recall(&plan.remembered, &cur),
+                };
+            }
+            let child = match &recalled {
+                Some(recalled) => Self::child_towards(&cur, recalled),
+                None => H::initial(&cur),
+            };
+            match child {
+                Some(child) => {
+                    plan.enter.push(child.clone());
+                    cur = child;
+                }
+                None => return cur,
+            }
+        }
+    }
+
+    /// Plan transition handled by `source`, while machine is in `leaf`.
+    /// Returns it together with new innermost state.
+    fn go(
+        &self,
+        leaf: &H::State,
+        source: &H::State,
+        target: H::State,
+    ) -> (Plan<H::State>, H::State) {
+        // Deepest state being proper ancestor of both source and target
+        let above_target = Self::path(H::parent(&target));
+        let lca = Self::path(H::parent(source))
+            .into_iter()
+            .find(|state| above_target.contains(state));
+
+        let mut exit = Self::path(Some(leaf.clone()));
+        if let Some(lca) = &lca {
+            exit.truncate(exit.iter().position(|state| state == lca).unwrap());
+        }
+        let remembered = exit
+            .iter()
+            .filter(|state| H::history(state) != History::None && *state != leaf)
+            .map(|state| (state.clone(), leaf.clone()))
+            .collect();
+
+        let mut enter = Self::path(Some(target.clone()));
+        if let Some(lca) = &lca {
+            enter.truncate(enter.iter().position(|state| state == lca).unwrap());
+        }
+        enter.reverse();
+
+        let mut plan = Plan {
+            exit,
+            remembered,
+            enter,
+        };
+        let leaf = self.descend(&mut plan, target);
+        (plan, leaf)
+    }
+}
+
+impl<H: Hierarchical> FSM for Hierarchy<H> {
+    type Event = H::Event;
+    type Response = H::Response;
+    type State = H::State;
+
+    fn new() -> Self {
+        Self {
+            inner:   H::new(),
+            started: false,
+            plan:    None,
+            memory:  Vec::new(),
+        }
+    }
+
+    fn trasnsit(
+        &mut self,
+        old_state: &<Self as FSM>::State,
+        ev: &<Self as FSM>::Event,
+    ) -> (
+        Option<<Self as FSM>::State>,
+        Option<<Self as FSM>::Response>,
+    ) {
+        self.plan = None;
+        let mut handler = Some(old_state.clone());
+        while let Some(source) = handler {
+            if let Some((target, response)) = self.inner.handle(&source, ev) {
+                let (plan, new_state) = match target {
+                    Some(target) => {
+                        let (plan, leaf) = self.go(old_state, &source, target);
+                        (plan, Some(leaf))
+                    }
+                    None => {
+                        let plan = Plan {
+                            exit:       Self::path(Some(old_state.clone())),
+                            remembered: Vec::new(),
+                            enter:      Vec::new(),
+                        };
+                        (plan, None)
+                    }
+                };
+                // Machine runs no actions when innermost state stays the same
+                if new_state.as_ref() != Some(old_state) {
+                    self.plan = Some(plan);
+                }
+                return (new_state, response);
+            }
+            handler = H::parent(&source);
+        }
+        (Some(old_state.clone()), None)
+    }
+
+    fn respond(
+        &mut self,
+        old_state: &<Self as FSM>::State,
+        new_state: &Option<<Self as FSM>::State>,
+        resp: &<Self as FSM>::Response,
+    ) {
+        self.inner.respond(old_state, new_state, resp)
+    }
+
+    fn on_shutdown(&mut self, state: &<Self as FSM>::State) -> Option<<Self as FSM>::Response> {
+        self.plan = None;
+        self.inner.shutdown(state)
+    }
+
+    fn priority(ev: &<Self as FSM>::Event) -> Priority { H::priority(ev) }
+
+    fn attach(&mut self, ctx: Context<<Self as FSM>::Event>) { self.inner.attach(ctx) }
+
+    fn on_enter(&mut self, state: &<Self as FSM>::State) {
+        if !self.started {
+            self.started = true;
+            assert!(
+                H::initial(state).is_none(),
+                "hierarchical machine must start in a leaf state"
+            );
+            for state in Self::path(Some(state.clone())).iter().rev() {
+                self.inner.enter(state);
+            }
+        } else if let Some(plan) = self.plan.take() {
+            for state in &plan.enter {
+                self.inner.enter(state);
+            }
+        }
+    }
+
+    fn on_exit(&mut self, state: &<Self as FSM>::State) {
+        // Without a transition in progress machine is stopped from the outside
+        let (exit, remembered) = match &mut self.plan {
+            Some(plan) => (mem::take(&mut plan.exit), mem::take(&mut plan.remembered)),
+            None => (Self::path(Some(state.clone())), Vec::new()),
+        };
+        for (composite, leaf) in remembered {
+            self.remember(composite, leaf);
+        }
+        for state in &exit {
+            self.inner.exit(state);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Recording recent transitions of a machine for post-mortem debugging.
+
+use crate::FSM;
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// Single step of a machine, as kept by history of
+/// [`Reactor::with_history`](crate::Reactor::with_history)
+///
+/// Step that panicked is recorded too, before machine is restarted by its
+/// [`Supervisor`](crate::Supervisor) or the panic ends it.
+///
+/// # Example
+/// ```
+/// # use pakr_fsm::*;
+/// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// # enum MyEv { Boom, Ping }
+/// # #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+/// # struct MyState;
+/// # struct MyFSM;
+/// # impl FSM for MyFSM {
+/// #     type Event = MyEv;
+/// #     type Response = &'static str;
+/// #     type State = MyState;
+/// #     fn new() -> Self { Self {} }
+/// #     fn trasnsit(&mut self, _: &MyState, ev: &MyEv) -> (Option<MyState>, Option<&'static str>) {
+/// #         match ev {
+/// #             MyEv::Boom => panic!("boom"),
+/// #             MyEv::Ping => (Some(MyState), Some("pong")),
+/// #         }
+/// #     }
+/// #     fn respond(&mut self, _: &MyState, _: &Option<MyState>, _: &&'static str) {}
+/// # }
+/// let fsm = ReactorBuilder::<MyFSM>::new()
+///     .history(4)
+///     .supervisor(Supervisor::new(Restart::LastState))
+///     .spawn()
+///     .unwrap();
+///
+/// fsm.send(MyEv::Boom).unwrap();
+/// assert_eq!(fsm.ask(MyEv::Ping), Ok(Some("pong")));
+///
+/// let steps: Vec<_> = fsm
+///     .history()
+///     .iter()
+///     .map(|rec| (rec.event, rec.to, rec.panicked))
+///     .collect();
+/// assert_eq!(
+///     steps,
+///     [
+///         (MyEv::Boom, None, true),
+///         (MyEv::Ping, Some(MyState), false)
+///     ]
+/// );
+/// ```
+pub struct Record<F: FSM> {
+    /// When event was processed, by clock of the machine
+    pub at:        Instant,
+    /// State before the step
+    pub from:      F::State,
+    /// Event processed
+    pub event:     F::Event,
+    /// State after the step, `None` if machine terminated or panicked
+    pub to:        Option<F::State>,
+    /// Whether transition gave a response
+    pub responded: bool,
+    /// Whether machine panicked during the step
+    pub panicked:  bool,
+}
+
+impl<F: FSM> Clone for Record<F>
+where
+    F::Event: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            at:        self.at,
+            from:      self.from.clone(),
+            event:     self.event.clone(),
+            to:        self.to.clone(),
+            responded: self.responded,
+            panicked:  self.panicked,
+        }
+    }
+}
+
+impl<F: FSM> fmt::Debug for Record<F>
+where
+    F::State: fmt::Debug,
+    F::Event: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Record")
+            .field("at", &self.at)
+            .field("from", &self.from)
+            .field("event", &self.event)
+            .field("to", &self.to)
+            .field("responded", &self.responded)
+            .field("panicked", &self.panicked)
+            .finish()
+    }
+}
+
+/// Records shared between machine thread and its `Reactor`
+pub(crate) type Log<F> = Arc<Mutex<VecDeque<Record<F>>>>;
+
+/// Writing end of history, owned by the machine thread
+pub(crate) struct Recorder<F: FSM> {
+    log:         Log<F>,
+    capacity:    usize,
+    /// Events are `Clone` only when history is on
+    clone_event: fn(&F::Event) -> F::Event,
+}
+
+impl<F: FSM> Recorder<F> {
+    /// Keep last `capacity` records
+    pub(crate) fn new(capacity: usize) -> Self
+    where
+        F::Event: Clone,
+    {
+        Self {
+            log: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            clone_event: F::Event::clone,
+        }
+    }
+
+    pub(crate) fn log(&self) -> Log<F> { self.log.clone() }
+
+    /// Start record of a step about to happen
+    pub(crate) fn begin(&self, at: Instant, from: &F::State, ev: &F::Event) -> Record<F> {
+        Record {
+            at,
+            from: from.clone(),
+            event: (self.clone_event)(ev),
+            to: None,
+            responded: false,
+            panicked: false,
+        }
+    }
+
+    /// Complete record with outcome of the step and store it, dropping the
+    /// oldest one if full.
+    pub(crate) fn commit(&self, mut record: Record<F>, to: Option<&F::State>, responded: bool) {
+        record.to = to.cloned();
+        record.responded = responded;
+        self.store(record);
+    }
+
+    /// Store record of a step that panicked
+    pub(crate) fn commit_panicked(&self, mut record: Record<F>) {
+        record.panicked = true;
+        self.store(record);
+    }
+
+    fn store(&self, record: Record<F>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut log = self.log.lock().unwrap();
+        if log.len() == self.capacity {
+            log.pop_front();
+        }
+        log.push_back(record);
+    }
+}
@@ -0,0 +1,473 @@
+//! Recording events of [`Reactor`](crate::Reactor) to rebuild the machine by
+//! replaying them.
+
+use crate::{Machine, Snapshot, SnapshotRef, FSM};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    mem,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// Single event recorded in a [`Journal`]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum Entry<E> {
+    /// Event taken from the queue
+    Event(E),
+    /// Event of an expired timer, set by the machine itself
+    Timer(E),
+}
+
+/// What a [`Journal`] holds: the latest snapshot, if it was ever compacted,
+/// and entries recorded after it, oldest first.
+pub struct Recovered<F: FSM> {
+    /// State to replay entries from, `None` to start machine afresh
+    pub snapshot: Option<Snapshot<F>>,
+    /// Entries to replay
+    pub entries:  Vec<Entry<F::Event>>,
+}
+
+/// Append-only record of events processed by a machine.
+///
+/// [`Reactor::with_journal`](crate::Reactor::with_journal) first rebuilds the
+/// machine from what the journal holds, by replaying its entries with
+/// [`Machine::step_quietly`], so `respond` is not called again. Then every
+/// event is appended before it is processed. This relies on `trasnsit`
+/// (together with `on_enter` and `on_exit`) being a pure mapping of state and
+/// event.
+///
+/// Deferred events are not recorded again, as replay defers and reconsiders
+/// them the same way. Journal is not compacted while machine keeps any
+/// deferred event, so they are never lost with the entries. Recorded timer
+/// events are taken out of the timers armed during replay instead of waiting
+/// for them. Timers pending when journal is compacted are lost, like with any
+/// [`Snapshot`].
+///
+/// # Example
+/// ```
+/// # use pakr_fsm::*;
+/// use serde::{Deserialize, Serialize};
+/// use std::sync::atomic::{AtomicU32, Ordering};
+///
+/// static RESPONDED: AtomicU32 = AtomicU32::new(0);
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+/// struct Add(u32);
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+/// struct Counting;
+///
+/// #[derive(Default, Serialize, Deserialize)]
+/// struct Sum(u32);
+///
+/// impl FSM for Sum {
+///     type Event = Add;
+///     type Response = u32;
+///     type State = Counting;
+///
+///     fn new() -> Self { Self::default() }
+///
+///     fn trasnsit(&mut self, _: &Counting, ev: &Add) -> (Option<Counting>, Option<u32>) {
+///         self.0 += ev.0;
+///         (Some(Counting), Some(self.0))
+///     }
+///
+///     fn respond(&mut self, _: &Counting, _: &Option<Counting>, _: &u32) {
+///         RESPONDED.fetch_add(1, Ordering::SeqCst);
+///     }
+/// }
+///
+/// let journal = MemoryJournal::new().compact_every(3);
+///
+/// let fsm = Reactor::<Sum>::with_journal(journal.clone()).unwrap();
+/// for n in 1 ..= 4 {
+///     fsm.send(Add(n)).unwrap();
+/// }
+/// assert_eq!(fsm.shutdown(Shutdown::Drain), Ok(None));
+/// assert_eq!(RESPONDED.load(Ordering::SeqCst), 4);
+/// // Snapshot taken after the third event, followed by the fourth one
+/// assert_eq!(journal.len(), 2);
+///
+/// let fsm = Reactor::<Sum>::with_journal(journal).unwrap();
+/// assert_eq!(fsm.ask(Add(5)), Ok(Some(15)));
+/// assert_eq!(RESPONDED.load(Ordering::SeqCst), 5);
+/// ```
+///
+/// Deferred events outlive the machine as well:
+/// ```
+/// # use pakr_fsm::*;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+/// enum Ev {
+///     Job(u32),
+///     Done,
+///     Noop,
+/// }
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+/// enum St {
+///     #[default]
+///     Idle,
+///     Busy,
+/// }
+///
+/// #[derive(Default, Serialize, Deserialize)]
+/// struct Worker {
+///     started: Vec<u32>,
+///     #[serde(skip)]
+///     ctx:     Option<Context<Ev>>,
+/// }
+///
+/// impl FSM for Worker {
+///     type Event = Ev;
+///     type Response = Vec<u32>;
+///     type State = St;
+///
+///     fn new() -> Self { Self::default() }
+///
+///     fn trasnsit(&mut self, state: &St, ev: &Ev) -> (Option<St>, Option<Vec<u32>>) {
+///         match (state, ev) {
+///             (St::Idle, Ev::Job(job)) => {
+///                 self.started.push(*job);
+///                 (Some(St::Busy), None)
+///             }
+///             (St::Busy, Ev::Job(_)) => {
+///                 self.ctx.as_ref().unwrap().defer();
+///                 (Some(St::Busy), None)
+///             }
+///             (St::Busy, Ev::Done) => (Some(St::Idle), None),
+///             (state, _) => (Some(*state), Some(self.started.clone())),
+///         }
+///     }
+///
+///     fn respond(&mut self, _: &St, _: &Option<St>, _: &Vec<u32>) {}
+///
+///     fn attach(&mut self, ctx: Context<Ev>) { self.ctx = Some(ctx) }
+/// }
+///
+/// let journal = MemoryJournal::new().compact_every(2);
+///
+/// let fsm = Reactor::<Worker>::with_journal(journal.clone()).unwrap();
+/// fsm.send(Ev::Job(1)).unwrap();
+/// fsm.send(Ev::Job(2)).unwrap();
+/// assert_eq!(fsm.shutdown(Shutdown::Drain), Ok(None));
+///
+/// let fsm = Reactor::<Worker>::with_journal(journal).unwrap();
+/// fsm.send(Ev::Done).unwrap();
+/// assert_eq!(fsm.ask(Ev::Noop), Ok(Some(vec![1, 2])));
+/// ```
+pub trait Journal<F: FSM>: Send {
+    /// Record `entry` at the end of the journal
+    fn append(&mut self, entry: Entry<&F::Event>) -> io::Result<()>;
+
+    /// Replace everything recorded with `snapshot`
+    fn compact(&mut self, snapshot: SnapshotRef<'_, F>) -> io::Result<()>;
+
+    /// Read back everything recorded
+    fn load(&mut self) -> io::Result<Recovered<F>>;
+
+    /// Number of entries after which journal is compacted, `None` to never
+    /// compact it. Default is `None`.
+    fn compact_every(&self) -> Option<usize> { None }
+}
+
+/// Line of a journal holding a snapshot. Entries are lines on their own.
+#[derive(Serialize)]
+#[serde(
+    rename = "Line",
+    bound(serialize = "F: Serialize, F::State: Serialize")
+)]
+enum SnapshotLine<'a, F: FSM> {
+    Snapshot(SnapshotRef<'a, F>),
+}
+
+/// Any line of a journal, as read back
+#[derive(Deserialize)]
+#[serde(bound(
+    deserialize = "F: DeserializeOwned, F::State: DeserializeOwned, F::Event: DeserializeOwned"
+))]
+enum Line<F: FSM> {
+    Snapshot(Snapshot<F>),
+    Event(F::Event),
+    Timer(F::Event),
+}
+
+fn encode(line: &impl Serialize) -> io::Result<String> {
+    let mut text = serde_json::to_string(line)?;
+    text.push('\n');
+    Ok(text)
+}
+
+/// Parse complete lines, snapshot restarting the record
+fn decode<'a, F>(lines: impl IntoIterator<Item = &'a str>) -> io::Result<Recovered<F>>
+where
+    F: FSM + DeserializeOwned,
+    F::State: DeserializeOwned,
+    F::Event: DeserializeOwned,
+{
+    let mut recovered = Recovered {
+        snapshot: None,
+        entries:  Vec::new(),
+    };
+    for line in lines {
+        match serde_json::from_str::<Line<F>>(line)? {
+            Line::Snapshot(snapshot) => {
+                recovered.snapshot = Some(snapshot);
+                recovered.entries.clear();
+            }
+            Line::Event(ev) => recovered.entries.push(Entry::Event(ev)),
+            Line::Timer(ev) => recovered.entries.push(Entry::Timer(ev)),
+        }
+    }
+    Ok(recovered)
+}
+
+/// [`Journal`] kept in memory, e.g. for tests.
+///
+/// Clones share the same record, so one can be kept to rebuild machine from,
+/// while another is given to a `Reactor`.
+#[derive(Clone, Default)]
+pub struct MemoryJournal {
+    lines:         Arc<Mutex<Vec<String>>>,
+    compact_every: Option<usize>,
+}
+
+impl MemoryJournal {
+    /// Create empty journal, never compacted
+    pub fn new() -> Self { Self::default() }
+
+    /// Compact journal after every `entries` entries
+    pub fn compact_every(mut self, entries: usize) -> Self {
+        self.compact_every = Some(entries);
+        self
+    }
+
+    /// Number of entries and snapshots recorded
+    pub fn len(&self) -> usize { self.lines.lock().unwrap().len() }
+
+    /// Checks whether nothing is recorded
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+}
+
+impl<F> Journal<F> for MemoryJournal
+where
+    F: FSM + Serialize + DeserializeOwned,
+    F::State: Serialize + DeserializeOwned,
+    F::Event: Serialize + DeserializeOwned,
+{
+    fn append(&mut self, entry: Entry<&F::Event>) -> io::Result<()> {
+        let line = encode(&entry)?;
+        self.lines.lock().unwrap().push(line);
+        Ok(())
+    }
+
+    fn compact(&mut self, snapshot: SnapshotRef<'_, F>) -> io::Result<()> {
+        let line = encode(&SnapshotLine::Snapshot(snapshot))?;
+        *self.lines.lock().unwrap() = vec![line];
+        Ok(())
+    }
+
+    fn load(&mut self) -> io::Result<Recovered<F>> {
+        decode(self.lines.lock().unwrap().iter().map(String::as_str))
+    }
+
+    fn compact_every(&self) -> Option<usize> { self.compact_every }
+}
+
+/// [`Journal`] kept in a file, one JSON line per entry.
+///
+/// Every entry is synced to disk before machine processes it. A line left
+/// incomplete by a crash is dropped when journal is loaded. Compaction writes
+/// the snapshot aside and then replaces the file with it.
+///
+/// # Example
+/// ```
+/// # use pakr_fsm::*;
+/// use serde::{Deserialize, Serialize};
+/// use std::{env, fs, io::Write};
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+/// struct Add(u32);
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+/// struct Counting;
+///
+/// #[derive(Default, Serialize, Deserialize)]
+/// struct Sum(u32);
+///
+/// impl FSM for Sum {
+///     type Event = Add;
+///     type Response = u32;
+///     type State = Counting;
+///
+///     fn new() -> Self { Self::default() }
+///
+///     fn trasnsit(&mut self, _: &Counting, ev: &Add) -> (Option<Counting>, Option<u32>) {
+///         self.0 += ev.0;
+///         (Some(Counting), Some(self.0))
+///     }
+///
+///     fn respond(&mut self, _: &Counting, _: &Option<Counting>, _: &u32) {}
+/// }
+///
+/// let path = env::temp_dir().join(format!("pakr-fsm-{}.journal", std::process::id()));
+/// let open = || FileJournal::open(&path).unwrap().compact_every(3);
+///
+/// let fsm = Reactor::<Sum>::with_journal(open()).unwrap();
+/// for n in 1 ..= 4 {
+///     fsm.send(Add(n)).unwrap();
+/// }
+/// assert_eq!(fsm.shutdown(Shutdown::Drain), Ok(None));
+///
+/// // Crash in the middle of writing an entry
+/// let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+/// file.write_all(br#"{"Event":"#).unwrap();
+///
+/// let fsm = Reactor::<Sum>::with_journal(open()).unwrap();
+/// assert_eq!(fsm.ask(Add(5)), Ok(Some(15)));
+/// assert_eq!(fsm.shutdown(Shutdown::Drain), Ok(None));
+///
+/// // Torn entry is gone, so the one written after it reads fine
+/// let fsm = Reactor::<Sum>::with_journal(open()).unwrap();
+/// assert_eq!(fsm.ask(Add(6)), Ok(Some(21)));
+/// assert_eq!(fsm.shutdown(Shutdown::Drain), Ok(None));
+/// fs::remove_file(&path).unwrap();
+/// ```
+pub struct FileJournal {
+    path:          PathBuf,
+    file:          File,
+    compact_every: Option<usize>,
+}
+
+impl FileJournal {
+    /// Open journal at `path`, creating empty one if there is none. Journal is
+    /// never compacted.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        Ok(Self {
+            file: Self::open_file(&path)?,
+            path,
+            compact_every: None,
+        })
+    }
+
+    /// Compact journal after every `entries` entries
+    pub fn compact_every(mut self, entries: usize) -> Self {
+        self.compact_every = Some(entries);
+        self
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()
+    }
+}
+
+impl<F> Journal<F> for FileJournal
+where
+    F: FSM + Serialize + DeserializeOwned,
+    F::State: Serialize + DeserializeOwned,
+    F::Event: Serialize + DeserializeOwned,
+{
+    fn append(&mut self, entry: Entry<&F::Event>) -> io::Result<()> {
+        let line = encode(&entry)?;
+        self.write(&line)
+    }
+
+    fn compact(&mut self, snapshot: SnapshotRef<'_, F>) -> io::Result<()> {
+        let line = encode(&SnapshotLine::Snapshot(snapshot))?;
+        let mut aside = self.path.clone().into_os_string();
+        aside.push(".tmp");
+        let mut file = File::create(&aside)?;
+        file.write_all(line.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&aside, &self.path)?;
+        self.file = Self::open_file(&self.path)?;
+        Ok(())
+    }
+
+    fn load(&mut self) -> io::Result<Recovered<F>> {
+        let text = fs::read_to_string(&self.path)?;
+        let complete = text.rfind('\n').map_or(0, |end| end + 1);
+        if complete < text.len() {
+            // Torn write, appending after it would corrupt the next line
+            self.file.set_len(complete as u64)?;
+        }
+        decode(text[.. complete].lines())
+    }
+
+    fn compact_every(&self) -> Option<usize> { self.compact_every }
+}
+
+/// Journal of a running `Reactor`
+pub(crate) struct Journaling<F: FSM> {
+    journal:  Box<dyn Journal<F>>,
+    /// Loaded entries not replayed yet
+    backlog:  Vec<Entry<F::Event>>,
+    /// Entries recorded since last compaction
+    recorded: usize,
+}
+
+impl<F: FSM> Journaling<F> {
+    pub(crate) fn new(journal: Box<dyn Journal<F>>, backlog: Vec<Entry<F::Event>>) -> Self {
+        Self {
+            journal,
+            recorded: backlog.len(),
+            backlog,
+        }
+    }
+
+    /// Bring freshly started `machine` up to date with loaded entries.
+    /// Deferred events are reconsidered before each entry, as they were when
+    /// entries were recorded.
+    pub(crate) fn replay(&mut self, mut machine: Machine<F>) -> Machine<F> {
+        for entry in mem::take(&mut self.backlog) {
+            while let Some(ev) = machine.take_replayed() {
+                machine.step_quietly(ev);
+            }
+            let ev = match entry {
+                Entry::Event(ev) => ev,
+                Entry::Timer(ev) => {
+                    machine.take_timer(&ev);
+                    ev
+                }
+            };
+            machine.step_quietly(ev);
+        }
+        machine
+    }
+
+    /// Record event about to be processed. Machine cannot go on without it.
+    pub(crate) fn record(&mut self, entry: Entry<&F::Event>) {
+        if let Err(err) = self.journal.append(entry) {
+            panic!("failed to write journal: {}", err);
+        }
+        self.recorded += 1;
+    }
+
+    /// Compact journal if enough entries were recorded, or at once if
+    /// `force`d, e.g. when machine no longer matches the record. Compaction
+    /// that is due waits while machine keeps deferred events, as snapshot
+    /// would lose them.
+    pub(crate) fn compact(&mut self, machine: &Machine<F>, force: bool) {
+        let due = self
+            .journal
+            .compact_every()
+            .is_some_and(|every| self.recorded >= every)
+            && !machine.has_deferred();
+        if !(force || due) || machine.is_terminated() {
+            return;
+        }
+        if let Err(err) = self.journal.compact(machine.snapshot()) {
+            panic!("failed to compact journal: {}", err);
+        }
+        self.recorded = 0;
+    }
+}
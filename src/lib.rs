@@ -58,13 +58,56 @@
 //!     fsm.send(MyEv::E1)?;
 //!     fsm.send(MyEv::E1)?;
 //!     let ans = fsm.join().unwrap();
-//!     println!("Final state result: {:?}",ans);
+//!     println!("Final state result: {:?}", ans);
 //!     Ok(())
 //! }
 //! ```
 
-use std::{sync::mpsc, thread};
-use std::any::Any;
+mod alarm;
+mod async_reactor;
+mod builder;
+mod clock;
+mod context;
+pub mod dot;
+mod error;
+pub mod executor;
+mod fallible;
+mod hierarchy;
+mod history;
+#[cfg(feature = "journal")]
+mod journal;
+mod machine;
+mod macros;
+mod orthogonal;
+mod queue;
+mod reactor;
+mod sender;
+#[cfg(feature = "serde")]
+mod snapshot;
+mod supervisor;
+mod trace;
+mod watch;
+
+pub use async_reactor::{AsyncReactor, AsyncSender, Driver, Join};
+pub use builder::ReactorBuilder;
+pub use clock::{Clock, ClockWaker, ManualClock, SystemClock};
+pub use context::{Context, TimerId};
+pub use error::ReactorError;
+pub use fallible::{ErrorPolicy, Fallible, TryFSM};
+pub use hierarchy::{Hierarchical, Hierarchy, History};
+pub use history::Record;
+#[cfg(feature = "journal")]
+pub use journal::{Entry, FileJournal, Journal, MemoryJournal, Recovered};
+pub use machine::{Machine, StepOutcome};
+pub use orthogonal::{AllEnd, AnyEnds, Orthogonal, Regions, Termination};
+pub use queue::{Control, Priority};
+pub use reactor::{DropPolicy, Reactor, Shutdown};
+pub use sender::{EventSender, PendingReply, Reply};
+#[cfg(feature = "serde")]
+pub use snapshot::{Snapshot, SnapshotRef};
+pub use supervisor::{PanicReport, Restart, Supervisor};
+pub use trace::MaybeDebug;
+pub use watch::StateWatch;
 
 /// Trait `FSM` engulfs transition logic and related datatypes.
 ///
@@ -73,14 +116,14 @@ use std::any::Any;
 /// state)
 pub trait FSM {
     /// Events are sent from outer world to influence state of machine
-    type Event: Send + Eq + PartialEq + 'static;
+    type Event: Send + Eq + PartialEq + MaybeDebug + 'static;
 
     /// Response are optional outcomes of transition
     type Response: Send + 'static;
 
     /// Current state of the machine. Default should init machine state to entry
-    /// one
-    type State: Eq + PartialEq + Default;
+    /// one. It is cloned to be observed from outside of the machine thread.
+    type State: Eq + PartialEq + Default + Clone + Send + MaybeDebug + 'static;
 
     /// Creating a new machine
     fn new() -> Self;
@@ -88,7 +131,8 @@ pub trait FSM {
     /// Mapping current state & event into a eventual new state and eventual
     /// response.
     ///
-    /// - A new state of `None` means machine termination, with response as return value
+    /// - A new state of `None` means machine termination, with response as
+    ///   return value
     /// - A response of `None` means no output given
     fn trasnsit(
         &mut self,
@@ -99,63 +143,44 @@ pub trait FSM {
         Option<<Self as FSM>::Response>,
     );
 
-    ///
+    /// Handling response of transition. Called only when `trasnsit` returned
+    /// some response.
     fn respond(
         &mut self,
         old_state: &<Self as FSM>::State,
         new_state: &Option<<Self as FSM>::State>,
         resp: &<Self as FSM>::Response,
     );
-}
-
-/// Reactor is `FSM` handle to interact and monitor
-pub struct Reactor<F: FSM> {
-    reactor: thread::JoinHandle<Option<F::Response>>,
-    chan: mpsc::Sender<F::Event>,
-}
 
-impl<F: FSM> Reactor<F> {
-    /// Create new `Reactor`.
+    /// Final words of machine stopped from the outside, e.g. by
+    /// [`Reactor::shutdown`] or once all senders are gone. Returned response
+    /// becomes the result of the machine.
     ///
-    /// Spawns associated `FSM` in separate thread, itializes it by calling its `new` and setting
-    /// state to `default`.
-    pub fn new() -> Self {
-        let (tx, rx) = mpsc::channel::<F::Event>();
-
-        let t = thread::spawn(move || {
-            let mut fsm = F::new();
-            let mut state = F::State::default();
-
-            while let Ok(ev) = rx.recv() {
-                let (new_state, response) = fsm.trasnsit(&state, &ev);
-                if let Some(response) = &response {
-                    fsm.respond(&state, &new_state, &response);
-                }
-
-                match new_state {
-                    None => return response,
-                    Some(new_state) => state = new_state,
-                }
-            }
-            return None;
-        });
-
-        Self {
-            reactor: t,
-            chan: tx,
-        }
+    /// Not called for machine that terminated by itself.
+    fn on_shutdown(&mut self, _state: &<Self as FSM>::State) -> Option<<Self as FSM>::Response> {
+        None
     }
 
-    /// Waits for `FSM` to complete.
+    /// Priority of `ev` in the event queue of [`Reactor`]. Default is
+    /// `Normal` for all events.
+    fn priority(_ev: &<Self as FSM>::Event) -> Priority { Priority::Normal }
+
+    /// Receive [`Context`] of the machine running this `FSM`, e.g. to schedule
+    /// timers from transitions.
     ///
-    /// Returns response of the last transition
-    pub fn join(self) -> thread::Result<Option<<F as FSM>::Response>> { self.reactor.join() }
+    /// Called once, right after `new` and before the initial `on_enter`.
+    fn attach(&mut self, _ctx: Context<<Self as FSM>::Event>) {}
 
-    /// Send event to `FSM`
-    pub fn send(&self, ev: <F as FSM>::Event) -> Result<(), mpsc::SendError<<F as FSM>::Event>> {
-        self.chan.send(ev)
-    }
+    /// Entry action of `state`.
+    ///
+    /// Called for the initial state when machine starts and for every new
+    /// state after a transition that changed state, following `respond`.
+    fn on_enter(&mut self, _state: &<Self as FSM>::State) {}
 
-    /// Clone `send` endpoint of `FSM` channel to be used in other places.
-    pub fn get_sender(&self) -> mpsc::Sender<F::Event> { self.chan.clone() }
+    /// Exit action of `state`.
+    ///
+    /// Called for the old state on every transition that changed state or
+    /// terminated machine, before `respond`, and for the current state of
+    /// machine stopped from the outside, after `on_shutdown`.
+    fn on_exit(&mut self, _state: &<Self as FSM>::State) {}
 }
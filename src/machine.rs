@@ -0,0 +1,310 @@
+//! In-place stepper, running `FSM` without any thread or channel.
+
+use crate::{sender::ReplyTo, Clock, Context, SystemClock, FSM};
+#[cfg(feature = "serde")]
+use crate::{Snapshot, SnapshotRef};
+use std::{
+    collections::VecDeque,
+    mem,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Result of a single [`Machine::step`]
+pub enum StepOutcome<'a, F: FSM> {
+    /// Machine is still running
+    Running {
+        /// State after the transition
+        state:    &'a F::State,
+        /// Response of the transition, if any
+        response: Option<F::Response>,
+    },
+    /// Machine terminated, with response of the last transition
+    Terminated(Option<F::Response>),
+}
+
+/// `Machine` owns `FSM` together with its state and drives it synchronously.
+///
+/// It runs exactly the same `trasnsit`/`respond` sequence as
+/// [`Reactor`](crate::Reactor), but in the caller's thread and only when
+/// asked to.
+///
+/// # Example
+/// ```
+/// # use pakr_fsm::*;
+/// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// # enum MyEv { E1, E2 }
+/// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// # enum MyState { S1, S2 }
+/// # impl Default for MyState {
+/// #     fn default() -> Self { Self::S1 }
+/// # }
+/// # struct MyFSM;
+/// # impl FSM for MyFSM {
+/// #     type Event = MyEv;
+/// #     type Response = &'static str;
+/// #     type State = MyState;
+/// #     fn new() -> Self { Self {} }
+/// #     fn trasnsit(
+/// #         &mut self,
+/// #         old_state: &Self::State,
+/// #         ev: &Self::Event,
+/// #     ) -> (Option<Self::State>, Option<Self::Response>) {
+/// #         match (old_state, ev) {
+/// #             (MyState::S1, MyEv::E1) => (None, Some("Quitting")),
+/// #             (MyState::S1, MyEv::E2) => (Some(MyState::S2), None),
+/// #             (MyState::S2, MyEv::E1) => (Some(MyState::S1), Some("S2@E1->S1")),
+/// #             (MyState::S2, MyEv::E2) => (Some(MyState::S2), Some("S2@E2->S2")),
+/// #         }
+/// #     }
+/// #     fn respond(&mut self, _: &MyState, _: &Option<MyState>, _: &&'static str) {}
+/// # }
+/// let mut m = Machine::<MyFSM>::new();
+///
+/// match m.step(MyEv::E2) {
+///     StepOutcome::Running {
+///         state,
+///         response,
+///     } => {
+///         assert_eq!(*state, MyState::S2);
+///         assert_eq!(response, None);
+///     }
+///     StepOutcome::Terminated(_) => unreachable!(),
+/// }
+///
+/// m.step(MyEv::E1);
+/// assert!(matches!(
+///     m.step(MyEv::E1),
+///     StepOutcome::Terminated(Some("Quitting"))
+/// ));
+/// assert!(m.is_terminated());
+/// ```
+pub struct Machine<F: FSM> {
+    fsm:        F,
+    state:      F::State,
+    terminated: bool,
+    /// Event being processed, kept for post-mortem after a panic
+    current:    Option<F::Event>,
+    /// Asking party of event being processed
+    reply_to:   Option<ReplyTo<F>>,
+    ctx:        Context<F::Event>,
+    /// When current state was entered
+    entered:    Instant,
+    /// Events deferred in current state, with their asking parties
+    deferred:   VecDeque<(F::Event, Option<ReplyTo<F>>)>,
+    /// Deferred events to be reconsidered after state change
+    replay:     VecDeque<(F::Event, Option<ReplyTo<F>>)>,
+}
+
+impl<F: FSM> Machine<F> {
+    /// Create new `Machine`, initializing `FSM` by calling its `new` and
+    /// setting state to `default`. Calls `on_enter` of the initial state.
+    pub fn new() -> Self { Self::from_parts(F::new(), F::State::default()) }
+
+    /// Create `Machine` from already built `FSM` and its state.
+    ///
+    /// Attaches fresh [`Context`] to `FSM` and calls `on_enter` of the initial
+    /// state.
+    pub fn from_parts(fsm: F, state: F::State) -> Self {
+        Self::with_clock(fsm, state, Arc::new(SystemClock))
+    }
+
+    /// Same as `from_parts`, but with timers and time in state measured by
+    /// `clock`.
+    pub fn with_clock(fsm: F, state: F::State, clock: Arc<dyn Clock>) -> Self {
+        Self::assemble(fsm, state, clock, true)
+    }
+
+    /// Same as `with_clock`, but `state` was already entered before, so its
+    /// `on_enter` is not called again.
+    pub(crate) fn resume(fsm: F, state: F::State, clock: Arc<dyn Clock>) -> Self {
+        Self::assemble(fsm, state, clock, false)
+    }
+
+    /// Resume `Machine` from `snapshot`, with fresh [`Context`] attached to
+    /// `FSM`. State of snapshot is not entered again.
+    #[cfg(feature = "serde")]
+    pub fn restore(snapshot: Snapshot<F>) -> Self {
+        Self::resume(snapshot.fsm, snapshot.state, Arc::new(SystemClock))
+    }
+
+    fn assemble(mut fsm: F, state: F::State, clock: Arc<dyn Clock>, enter: bool) -> Self {
+        let ctx = Context::new(clock);
+        fsm.attach(ctx.clone());
+        if enter {
+            fsm.on_enter(&state);
+        }
+        ctx.settle(false);
+        Self {
+            fsm,
+            state,
+            terminated: false,
+            current: None,
+            reply_to: None,
+            entered: ctx.now(),
+            ctx,
+            deferred: VecDeque::new(),
+            replay: VecDeque::new(),
+        }
+    }
+
+    /// Feed single event to `FSM`.
+    ///
+    /// Runs `trasnsit`, then, if state changed or machine terminated,
+    /// `on_exit` of the old state, then `respond` (if there was a response),
+    /// then `on_enter` of the new state. Timers of the old state are cancelled
+    /// if it was exited. Once machine terminated, further steps are no-op
+    /// returning `Terminated(None)`.
+    ///
+    /// Event deferred by `trasnsit` with [`Context::defer`] is kept, and
+    /// nothing else happens. After the next state change all deferred events
+    /// become available, in order, from `take_replayed`.
+    pub fn step(&mut self, ev: F::Event) -> StepOutcome<'_, F> { self.advance(ev, true) }
+
+    /// Same as `step`, but `respond` is not called, e.g. when replaying events
+    /// already processed once. Entry and exit actions still run.
+    pub fn step_quietly(&mut self, ev: F::Event) -> StepOutcome<'_, F> { self.advance(ev, false) }
+
+    fn advance(&mut self, ev: F::Event, respond: bool) -> StepOutcome<'_, F> {
+        if self.terminated {
+            return StepOutcome::Terminated(None);
+        }
+
+        let ev = self.current.insert(ev);
+        let (new_state, response) = self.fsm.trasnsit(&self.state, ev);
+        if self.ctx.is_deferring() {
+            let reply_to = self.reply_to.take();
+            self.deferred
+                .extend(self.current.take().map(|ev| (ev, reply_to)));
+            self.ctx.settle(false);
+            return StepOutcome::Running {
+                state:    &self.state,
+                response: None,
+            };
+        }
+        let changed = new_state.as_ref() != Some(&self.state);
+        if changed {
+            self.fsm.on_exit(&self.state);
+        }
+        if let (true, Some(response)) = (respond, &response) {
+            self.fsm.respond(&self.state, &new_state, response);
+        }
+
+        match new_state {
+            None => {
+                self.terminated = true;
+                self.current = None;
+                self.ctx.cancel_all();
+                self.deferred.clear();
+                self.replay.clear();
+                StepOutcome::Terminated(response)
+            }
+            Some(new_state) => {
+                self.state = new_state;
+                if changed {
+                    self.entered = self.ctx.now();
+                    self.fsm.on_enter(&self.state);
+                    // Older deferrals go first, including ones deferred again
+                    let mut replay = mem::take(&mut self.deferred);
+                    replay.append(&mut self.replay);
+                    self.replay = replay;
+                }
+                self.ctx.settle(changed);
+                self.current = None;
+                StepOutcome::Running {
+                    state: &self.state,
+                    response,
+                }
+            }
+        }
+    }
+
+    /// Stop machine from the outside, returning final response given by
+    /// [`FSM::on_shutdown`], which is followed by `on_exit` of the current
+    /// state. No-op returning `None` for terminated machine.
+    pub fn shutdown(&mut self) -> Option<F::Response> {
+        if self.terminated {
+            return None;
+        }
+        let response = self.fsm.on_shutdown(&self.state);
+        self.fsm.on_exit(&self.state);
+        self.terminated = true;
+        self.ctx.cancel_all();
+        self.deferred.clear();
+        self.replay.clear();
+        response
+    }
+
+    /// Current state of the machine. After termination it is the last state
+    /// machine was in.
+    pub fn state(&self) -> &F::State { &self.state }
+
+    /// Borrow `FSM` and its state to be serialized, see [`Snapshot`]
+    #[cfg(feature = "serde")]
+    pub fn snapshot(&self) -> SnapshotRef<'_, F> {
+        SnapshotRef {
+            fsm:   &self.fsm,
+            state: &self.state,
+        }
+    }
+
+    /// Shared access to the `FSM`
+    pub fn fsm(&self) -> &F { &self.fsm }
+
+    /// Exclusive access to the `FSM`
+    pub fn fsm_mut(&mut self) -> &mut F { &mut self.fsm }
+
+    /// Checks whether machine has terminated
+    pub fn is_terminated(&self) -> bool { self.terminated }
+
+    /// Earliest deadline of timers scheduled by `FSM`
+    pub fn next_deadline(&self) -> Option<Instant> { self.ctx.next_deadline() }
+
+    /// Remove the earliest timer that is already due, returning its event to
+    /// be fed to `step`.
+    pub fn take_expired(&mut self) -> Option<F::Event> { self.ctx.take_expired() }
+
+    /// Cancel the earliest timer of event `ev`, as it was already delivered
+    #[cfg(feature = "journal")]
+    pub(crate) fn take_timer(&mut self, ev: &F::Event) -> bool { self.ctx.take_matching(ev) }
+
+    /// Remove the oldest deferred event that is due for reconsideration, to
+    /// be fed to `step` again.
+    pub fn take_replayed(&mut self) -> Option<F::Event> {
+        self.replay.pop_front().map(|(ev, _)| ev)
+    }
+
+    /// Checks whether any deferred event is kept, waiting or due for
+    /// reconsideration
+    #[cfg(feature = "journal")]
+    pub(crate) fn has_deferred(&self) -> bool {
+        !self.deferred.is_empty() || !self.replay.is_empty()
+    }
+
+    /// Same as `take_replayed`, also returning asking party of the event
+    pub(crate) fn take_replayed_asked(&mut self) -> Option<(F::Event, Option<ReplyTo<F>>)> {
+        self.replay.pop_front()
+    }
+
+    /// Asking party of the event about to be stepped. It is kept with the
+    /// event if it gets deferred, to be replied to once it is handled.
+    pub(crate) fn set_reply_to(&mut self, reply_to: Option<ReplyTo<F>>) { self.reply_to = reply_to }
+
+    /// Asking party of the event just stepped, unless it was deferred
+    pub(crate) fn take_reply_to(&mut self) -> Option<ReplyTo<F>> { self.reply_to.take() }
+
+    /// Time spent in current state so far
+    pub fn time_in_state(&self) -> Duration {
+        self.ctx.now().saturating_duration_since(self.entered)
+    }
+
+    /// Event that was being processed when `step` unwound
+    pub(crate) fn take_current(&mut self) -> Option<F::Event> { self.current.take() }
+
+    /// Take machine apart
+    pub fn into_parts(self) -> (F, F::State) { (self.fsm, self.state) }
+}
+
+impl<F: FSM> Default for Machine<F> {
+    fn default() -> Self { Self::new() }
+}
@@ -0,0 +1,287 @@
+//! Declarative transition tables.
+
+/// Generate state and event enums together with complete [`FSM`](crate::FSM)
+/// impl from a transition table.
+///
+/// Machine type must implement `Default`, which is used as `FSM::new`. Both
+/// enums derive `Copy`, `Clone`, `Eq`, `PartialEq`, `Hash` and `Debug`, state
+/// one also `Default`, so initial state is marked with `#[default]`. Both
+/// also implement [`Enumerable`](crate::dot::Enumerable), and machine
+/// implements [`Transitions`](crate::dot::Transitions) listing rows of the
+/// table, so it can be rendered with [`dot::render`](crate::dot::render).
+///
+/// Each row of `transitions` reads
+/// `State + Event [guard] => Next {action} / response;`, where
+///
+/// - `[guard]` is optional name of `fn(&self) -> bool` method of the machine;
+///   row applies only if it returns `true`,
+/// - `Next` is either a state, or `!` to terminate machine,
+/// - `{action}` is optional name of `fn(&mut self)` method run on transition,
+/// - `/ response` is optional expression, becoming response of the transition.
+///
+/// Rows are tried top to bottom. Pair of state and event without matching
+/// row leaves machine in its state, with no response. Optional
+/// `respond = method;` names method receiving arguments of `FSM::respond`.
+///
+/// # Example
+/// ```
+/// use pakr_fsm::*;
+///
+/// #[derive(Default)]
+/// struct Turnstile {
+///     coins: u32,
+/// }
+///
+/// impl Turnstile {
+///     fn is_full(&self) -> bool { self.coins >= 2 }
+///
+///     fn count(&mut self) { self.coins += 1 }
+/// }
+///
+/// fsm! {
+///     pub enum Gate {
+///         #[default]
+///         Locked,
+///         Unlocked,
+///     }
+///
+///     pub enum Action {
+///         Coin,
+///         Push,
+///     }
+///
+///     impl FSM for Turnstile {
+///         type Response = &'static str;
+///
+///         transitions {
+///             Locked + Coin [is_full] => ! / "full";
+///             Locked + Coin => Unlocked {count} / "unlocked";
+///             Unlocked + Push => Locked;
+///             Unlocked + Coin => Unlocked / "refund";
+///         }
+///     }
+/// }
+///
+/// let mut m = Machine::<Turnstile>::new();
+/// assert!(matches!(
+///     m.step(Action::Push),
+///     StepOutcome::Running {
+///         state: Gate::Locked,
+///         ..
+///     }
+/// ));
+/// m.step(Action::Coin);
+/// assert_eq!(*m.state(), Gate::Unlocked);
+/// m.step(Action::Push);
+/// m.step(Action::Coin);
+/// m.step(Action::Push);
+/// assert!(matches!(
+///     m.step(Action::Coin),
+///     StepOutcome::Terminated(Some("full"))
+/// ));
+///
+/// assert!(dot::render::<Turnstile>()
+///     .contains(r#""Locked" -> "__end" [label="Coin [is_full] / \"full\""];"#));
+/// ```
+///
+/// Duplicate or conflicting rows are rejected at compile time:
+/// ```compile_fail
+/// # use pakr_fsm::*;
+/// # #[derive(Default)]
+/// # struct Turnstile;
+/// fsm! {
+///     pub enum Gate { #[default] Locked, Unlocked }
+///     pub enum Action { Coin, Push }
+///
+///     impl FSM for Turnstile {
+///         type Response = ();
+///
+///         transitions {
+///             Locked + Coin => Unlocked;
+///             Locked + Coin => Locked;
+///         }
+///     }
+/// }
+/// ```
+///
+/// So are rows repeating guard of an earlier one:
+/// ```compile_fail
+/// # use pakr_fsm::*;
+/// # #[derive(Default)]
+/// # struct Turnstile;
+/// # impl Turnstile {
+/// #     fn is_full(&self) -> bool { true }
+/// # }
+/// fsm! {
+///     pub enum Gate { #[default] Locked, Unlocked }
+///     pub enum Action { Coin, Push }
+///
+///     impl FSM for Turnstile {
+///         type Response = ();
+///
+///         transitions {
+///             Locked + Coin [is_full] => Unlocked;
+///             Locked + Coin [is_full] => Locked;
+///         }
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! fsm {
+    (@next $state:ident !) => {
+        None
+    };
+    (@next $state:ident $next:ident) => {
+        Some($state::$next)
+    };
+    (@response) => {
+        None
+    };
+    (@response $response:expr) => {
+        Some($response)
+    };
+    (@label) => {
+        None
+    };
+    (@label $response:expr) => {
+        Some(stringify!($response).to_string())
+    };
+    (@guard) => {
+        None
+    };
+    (@guard $guard:ident) => {
+        Some(stringify!($guard))
+    };
+    (
+        $(#[$sattr:meta])*
+        $svis:vis enum $state:ident {
+            $($(#[$stattr:meta])* $st:ident),+ $(,)?
+        }
+
+        $(#[$eattr:meta])*
+        $evis:vis enum $event:ident {
+            $($(#[$evattr:meta])* $ev:ident),+ $(,)?
+        }
+
+        impl FSM for $machine:ty {
+            type Response = $resp:ty;
+            $(respond = $respond:ident;)?
+
+            transitions {
+                $(
+                    $from:ident + $on:ident $([$guard:ident])? => $to:tt
+                        $({$action:ident})? $(/ $response:expr)?;
+                )*
+            }
+        }
+    ) => {
+        $(#[$sattr])*
+        #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+        $svis enum $state {
+            $($(#[$stattr])* $st),+
+        }
+
+        $(#[$eattr])*
+        #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+        $evis enum $event {
+            $($(#[$evattr])* $ev),+
+        }
+
+        impl $crate::dot::Enumerable for $state {
+            fn variants() -> Vec<Self> { vec![$($state::$st),+] }
+        }
+
+        impl $crate::dot::Enumerable for $event {
+            fn variants() -> Vec<Self> { vec![$($event::$ev),+] }
+        }
+
+        impl $crate::dot::Transitions for $machine {
+            fn transitions() -> Vec<$crate::dot::Edge<Self>> {
+                vec![$(
+                    $crate::dot::Edge {
+                        from: $state::$from,
+                        event: $event::$on,
+                        guard: $crate::fsm!(@guard $($guard)?),
+                        to: $crate::fsm!(@next $state $to),
+                        response: $crate::fsm!(@label $($response)?),
+                    }
+                ),*]
+            }
+        }
+
+        // Row following an unguarded one, or one with the same guard, for the
+        // same state and event would never apply
+        const _: () = {
+            const fn shadows(earlier: Option<&str>, later: Option<&str>) -> bool {
+                let (earlier, later) = match (earlier, later) {
+                    (None, _) => return true,
+                    (Some(earlier), Some(later)) => (earlier.as_bytes(), later.as_bytes()),
+                    (Some(_), None) => return false,
+                };
+                if earlier.len() != later.len() {
+                    return false;
+                }
+                let mut k = 0;
+                while k < earlier.len() {
+                    if earlier[k] != later[k] {
+                        return false;
+                    }
+                    k += 1;
+                }
+                true
+            }
+
+            let rows: &[(usize, usize, Option<&str>)] = &[
+                $(($state::$from as usize, $event::$on as usize, $crate::fsm!(@guard $($guard)?)),)*
+            ];
+            let mut i = 0;
+            while i < rows.len() {
+                let mut j = i + 1;
+                while j < rows.len() {
+                    if rows[i].0 == rows[j].0 && rows[i].1 == rows[j].1 && shadows(rows[i].2, rows[j].2) {
+                        panic!("duplicate or conflicting transition rows");
+                    }
+                    j += 1;
+                }
+                i += 1;
+            }
+        };
+
+        impl $crate::FSM for $machine {
+            type Event = $event;
+            type Response = $resp;
+            type State = $state;
+
+            fn new() -> Self { ::std::default::Default::default() }
+
+            fn trasnsit(
+                &mut self,
+                old_state: &$state,
+                ev: &$event,
+            ) -> (Option<$state>, Option<$resp>) {
+                match (old_state, ev) {
+                    $(
+                        ($state::$from, $event::$on) $(if self.$guard())? => {
+                            $(self.$action();)?
+                            (
+                                $crate::fsm!(@next $state $to),
+                                $crate::fsm!(@response $($response)?),
+                            )
+                        }
+                    )*
+                    #[allow(unreachable_patterns)]
+                    _ => (Some(*old_state), None),
+                }
+            }
+
+            fn respond(
+                &mut self,
+                _old_state: &$state,
+                _new_state: &Option<$state>,
+                _resp: &$resp,
+            ) {
+                $(self.$respond(_old_state, _new_state, _resp);)?
+            }
+        }
+    };
+}
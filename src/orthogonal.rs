@@ -0,0 +1,393 @@
+//! Orthogonal regions: independent machines reacting to the same events.
+
+use crate::{Context, Priority, FSM};
+use std::marker::PhantomData;
+
+/// When [`Orthogonal`] machine terminates, given which of its regions ended
+pub trait Termination {
+    /// Whether machine ends, given whether its `first` and `second` regions
+    /// ended
+    fn ends(first: bool, second: bool) -> bool;
+}
+
+/// Machine ends as soon as any of its regions does
+pub struct AnyEnds;
+
+impl Termination for AnyEnds {
+    fn ends(first: bool, second: bool) -> bool { first || second }
+}
+
+/// Machine ends only once all its regions did. Ended region no longer gets
+/// any event.
+pub struct AllEnd;
+
+impl Termination for AllEnd {
+    fn ends(first: bool, second: bool) -> bool { first && second }
+}
+
+/// State of [`Orthogonal`] machine: states of its regions, `None` for region
+/// that ended.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Regions<A, B>(pub Option<A>, pub Option<B>);
+
+impl<A: Default, B: Default> Default for Regions<A, B> {
+    fn default() -> Self { Self(Some(A::default()), Some(B::default())) }
+}
+
+/// Contexts of the first and second region
+type RegionContexts<E> = (Context<E>, Context<E>);
+
+/// States of regions around the last transition
+struct Step<A, B> {
+    old:         Regions<A, B>,
+    new:         Regions<A, B>,
+    terminating: bool,
+}
+
+/// Adapter running two `FSM`s as orthogonal regions of a single machine.
+///
+/// Every event is given to `trasnsit` of each running region, and responses
+/// of regions are paired. Entry and exit actions run only for regions that
+/// changed state, so regions are as independent as separate machines. When
+/// machine ends, as decided by policy `P`, or is stopped from the outside,
+/// regions still running are exited.
+/// More regions are had by nesting `Orthogonal`s.
+///
+/// Each region gets its own [`Context`], so its timers are cancelled only when
+/// that region changes state, and `cancel_all` cancels only its timers.
+/// Regions cannot defer events, as other regions have already handled them.
+///
+/// # Example
+/// ```
+/// use pakr_fsm::*;
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// enum Ev {
+///     Sleep,
+///     Wake,
+///     Plug,
+///     Unplug,
+///     Remove,
+/// }
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+/// enum Power {
+///     #[default]
+///     On,
+///     Standby,
+/// }
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+/// enum Link {
+///     #[default]
+///     Down,
+///     Up,
+/// }
+///
+/// struct PowerFSM;
+///
+/// impl FSM for PowerFSM {
+///     type Event = Ev;
+///     type Response = &'static str;
+///     type State = Power;
+///
+///     fn new() -> Self { Self }
+///
+///     fn trasnsit(&mut self, state: &Power, ev: &Ev) -> (Option<Power>, Option<&'static str>) {
+///         match (state, ev) {
+///             (Power::On, Ev::Sleep) => (Some(Power::Standby), Some("zzz")),
+///             (Power::Standby, Ev::Wake) => (Some(Power::On), None),
+///             (_, Ev::Remove) => (None, Some("power gone")),
+///             _ => (Some(*state), None),
+///         }
+///     }
+///
+///     fn respond(&mut self, _: &Power, _: &Option<Power>, _: &&'static str) {}
+/// }
+///
+/// struct LinkFSM;
+///
+/// impl FSM for LinkFSM {
+///     type Event = Ev;
+///     type Response = u32;
+///     type State = Link;
+///
+///     fn new() -> Self { Self }
+///
+///     fn trasnsit(&mut self, state: &Link, ev: &Ev) -> (Option<Link>, Option<u32>) {
+///         match (state, ev) {
+///             (Link::Down, Ev::Plug) => (Some(Link::Up), Some(1000)),
+///             (Link::Up, Ev::Unplug | Ev::Sleep) => (Some(Link::Down), Some(0)),
+///             _ => (Some(*state), None),
+///         }
+///     }
+///
+///     fn respond(&mut self, _: &Link, _: &Option<Link>, _: &u32) {}
+/// }
+///
+/// let fsm = Reactor::<Orthogonal<PowerFSM, LinkFSM>>::new();
+/// assert_eq!(fsm.ask(Ev::Plug), Ok(Some((None, Some(1000)))));
+/// assert_eq!(fsm.state(), Regions(Some(Power::On), Some(Link::Up)));
+///
+/// // Both regions react to the same event
+/// assert_eq!(fsm.ask(Ev::Sleep), Ok(Some((Some("zzz"), Some(0)))));
+/// assert_eq!(fsm.state(), Regions(Some(Power::Standby), Some(Link::Down)));
+///
+/// // By default machine ends with its first region
+/// fsm.send(Ev::Remove).unwrap();
+/// assert_eq!(fsm.join(), Ok(Some((Some("power gone"), None))));
+///
+/// // Regions may be built beforehand
+/// let fsm: Reactor<Orthogonal<PowerFSM, LinkFSM, AllEnd>> =
+///     Reactor::from_parts(Orthogonal::new(PowerFSM, LinkFSM), Regions::default());
+/// fsm.send(Ev::Remove).unwrap();
+/// assert_eq!(fsm.ask(Ev::Plug), Ok(Some((None, Some(1000)))));
+/// ```
+///
+/// Timers of a region are not affected by other regions:
+/// ```
+/// use pakr_fsm::*;
+/// use std::{sync::Arc, time::Duration};
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// enum Ev {
+///     Call,
+///     NoAnswer,
+///     Toggle,
+/// }
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+/// enum Line {
+///     #[default]
+///     Idle,
+///     Ringing,
+///     Missed,
+/// }
+///
+/// #[derive(Default)]
+/// struct Phone {
+///     ctx: Option<Context<Ev>>,
+/// }
+///
+/// impl FSM for Phone {
+///     type Event = Ev;
+///     type Response = ();
+///     type State = Line;
+///
+///     fn new() -> Self { Self::default() }
+///
+///     fn trasnsit(&mut self, state: &Line, ev: &Ev) -> (Option<Line>, Option<()>) {
+///         match (state, ev) {
+///             (Line::Idle, Ev::Call) => (Some(Line::Ringing), None),
+///             (Line::Ringing, Ev::NoAnswer) => (Some(Line::Missed), None),
+///             _ => (Some(*state), None),
+///         }
+///     }
+///
+///     fn respond(&mut self, _: &Line, _: &Option<Line>, _: &()) {}
+///
+///     fn attach(&mut self, ctx: Context<Ev>) { self.ctx = Some(ctx) }
+///
+///     fn on_enter(&mut self, state: &Line) {
+///         if *state == Line::Ringing {
+///             let ctx = self.ctx.as_ref().unwrap();
+///             ctx.schedule(Duration::from_millis(50), Ev::NoAnswer);
+///         }
+///     }
+/// }
+///
+/// struct Lamp;
+///
+/// impl FSM for Lamp {
+///     type Event = Ev;
+///     type Response = ();
+///     type State = bool;
+///
+///     fn new() -> Self { Self }
+///
+///     fn trasnsit(&mut self, lit: &bool, ev: &Ev) -> (Option<bool>, Option<()>) {
+///         (Some(*lit ^ (*ev == Ev::Toggle)), None)
+///     }
+///
+///     fn respond(&mut self, _: &bool, _: &Option<bool>, _: &()) {}
+/// }
+///
+/// let clock = ManualClock::new();
+/// let mut m = Machine::with_clock(
+///     Orthogonal::<Phone, Lamp>::new(Phone::new(), Lamp),
+///     Regions::default(),
+///     Arc::new(clock.clone()),
+/// );
+/// m.step(Ev::Call);
+/// m.step(Ev::Toggle);
+/// assert_eq!(*m.state(), Regions(Some(Line::Ringing), Some(true)));
+///
+/// clock.advance(Duration::from_millis(50));
+/// let missed = m.take_expired().unwrap();
+/// m.step(missed);
+/// assert_eq!(*m.state(), Regions(Some(Line::Missed), Some(true)));
+/// ```
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Orthogonal<A: FSM, B: FSM, P = AnyEnds> {
+    first:  A,
+    second: B,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    step:   Option<Step<A::State, B::State>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    ctx:    Option<RegionContexts<A::Event>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    policy: PhantomData<P>,
+}
+
+impl<A: FSM, B: FSM, P> Orthogonal<A, B, P> {
+    /// Combine already built regions, e.g. for [`Reactor::from_parts`]
+    ///
+    /// [`Reactor::from_parts`]: crate::Reactor::from_parts
+    pub fn new(first: A, second: B) -> Self {
+        Self {
+            first,
+            second,
+            step: None,
+            ctx: None,
+            policy: PhantomData,
+        }
+    }
+
+    /// Shared access to the first region
+    pub fn first(&self) -> &A { &self.first }
+
+    /// Exclusive access to the first region
+    pub fn first_mut(&mut self) -> &mut A { &mut self.first }
+
+    /// Shared access to the second region
+    pub fn second(&self) -> &B { &self.second }
+
+    /// Exclusive access to the second region
+    pub fn second_mut(&mut self) -> &mut B { &mut self.second }
+}
+
+impl<A, B, P> FSM for Orthogonal<A, B, P>
+where
+    A: FSM,
+    B: FSM<Event = A::Event>,
+    P: Termination,
+{
+    type Event = A::Event;
+    type Response = (Option<A::Response>, Option<B::Response>);
+    type State = Regions<A::State, B::State>;
+
+    fn new() -> Self {
+        Self {
+            first:  A::new(),
+            second: B::new(),
+            step:   None,
+            ctx:    None,
+            policy: PhantomData,
+        }
+    }
+
+    fn trasnsit(
+        &mut self,
+        old_state: &<Self as FSM>::State,
+        ev: &<Self as FSM>::Event,
+    ) -> (
+        Option<<Self as FSM>::State>,
+        Option<<Self as FSM>::Response>,
+    ) {
+        let (first, first_resp) = match &old_state.0 {
+            Some(state) => self.first.trasnsit(state, ev),
+            None => (None, None),
+        };
+        let (second, second_resp) = match &old_state.1 {
+            Some(state) => self.second.trasnsit(state, ev),
+            None => (None, None),
+        };
+
+        let new_state = Regions(first, second);
+        let terminating = P::ends(new_state.0.is_none(), new_state.1.is_none());
+        let response = match (first_resp, second_resp) {
+            (None, None) => None,
+            responses => Some(responses),
+        };
+        self.step = Some(Step {
+            old: old_state.clone(),
+            new: new_state.clone(),
+            terminating,
+        });
+        (if terminating { None } else { Some(new_state) }, response)
+    }
+
+    fn respond(
+        &mut self,
+        old_state: &<Self as FSM>::State,
+        _new_state: &Option<<Self as FSM>::State>,
+        resp: &<Self as FSM>::Response,
+    ) {
+        // New states of regions are known even when machine terminates
+        let new = match &self.step {
+            Some(step) => &step.new,
+            None => return,
+        };
+        if let (Some(old), Some(resp)) = (&old_state.0, &resp.0) {
+            self.first.respond(old, &new.0, resp);
+        }
+        if let (Some(old), Some(resp)) = (&old_state.1, &resp.1) {
+            self.second.respond(old, &new.1, resp);
+        }
+    }
+
+    fn on_shutdown(&mut self, state: &<Self as FSM>::State) -> Option<<Self as FSM>::Response> {
+        self.step = None;
+        let first = state.0.as_ref().and_then(|s| self.first.on_shutdown(s));
+        let second = state.1.as_ref().and_then(|s| self.second.on_shutdown(s));
+        match (first, second) {
+            (None, None) => None,
+            responses => Some(responses),
+        }
+    }
+
+    fn priority(ev: &<Self as FSM>::Event) -> Priority { A::priority(ev).max(B::priority(ev)) }
+
+    fn attach(&mut self, ctx: Context<<Self as FSM>::Event>) {
+        let regions = (ctx.region(), ctx.region());
+        self.first.attach(regions.0.clone());
+        self.second.attach(regions.1.clone());
+        self.ctx = Some(regions);
+    }
+
+    fn on_enter(&mut self, state: &<Self as FSM>::State) {
+        // Machine starting enters all regions
+        let old = self.step.as_ref().map(|step| &step.old);
+        if let Some(s) = &state.0 {
+            if old.is_none_or(|old| old.0.as_ref() != Some(s)) {
+                self.first.on_enter(s);
+            }
+        }
+        if let Some(s) = &state.1 {
+            if old.is_none_or(|old| old.1.as_ref() != Some(s)) {
+                self.second.on_enter(s);
+            }
+        }
+    }
+
+    fn on_exit(&mut self, state: &<Self as FSM>::State) {
+        // Machine stopped from the outside exits all regions. Timers of regions
+        // staying in their states outlive the state of the machine.
+        let step = self.step.as_ref();
+        if let Some(s) = &state.0 {
+            if step.is_none_or(|step| step.terminating || step.new.0.as_ref() != Some(s)) {
+                self.first.on_exit(s);
+            } else if let Some((ctx, _)) = &self.ctx {
+                ctx.keep();
+            }
+        }
+        if let Some(s) = &state.1 {
+            if step.is_none_or(|step| step.terminating || step.new.1.as_ref() != Some(s)) {
+                self.second.on_exit(s);
+            } else if let Some((_, ctx)) = &self.ctx {
+                ctx.keep();
+            }
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! Event queue of [`Reactor`](crate::Reactor), optionally bounded, with
+//! priority lanes and a control lane.
+
+use crate::{Clock, ReactorError};
+use std::{
+    collections::VecDeque,
+    sync::{mpsc, Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Priority of an event, as given by [`FSM::priority`](crate::FSM::priority).
+///
+/// Queued events of higher priority are processed first, events of the same
+/// priority in order they were sent.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub enum Priority {
+    /// Processed when nothing else is queued
+    Low,
+    /// Priority of all events, unless stated otherwise
+    #[default]
+    Normal,
+    /// Processed before any other event
+    High,
+}
+
+impl Priority {
+    /// Lanes are served from the lowest index
+    fn lane(self) -> usize {
+        match self {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        }
+    }
+}
+
+/// Reactor-level command, sent with
+/// [`Reactor::control`](crate::Reactor::control).
+///
+/// Commands travel in their own lane, that is served before any event and is
+/// never full.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Control {
+    /// Stop processing events (including timers) until `Resume`. Events can
+    /// still be queued meanwhile.
+    Pause,
+    /// Continue processing events
+    Resume,
+    /// Refuse further events at once, process ones already queued, then end
+    /// machine
+    Drain,
+    /// End machine at once, discarding queued events
+    Stop,
+}
+
+/// Item taken from the queue
+pub(crate) enum Received<T> {
+    Item(T),
+    Control(Control),
+}
+
+struct Inner<T> {
+    lanes:    [VecDeque<T>; 3],
+    control:  VecDeque<Control>,
+    capacity: Option<usize>,
+    senders:  usize,
+    receiver: bool,
+    /// No more items are accepted, receiver drains the rest
+    closed:   bool,
+}
+
+impl<T> Inner<T> {
+    fn len(&self) -> usize { self.lanes.iter().map(VecDeque::len).sum() }
+
+    fn is_full(&self) -> bool { self.capacity.is_some_and(|cap| self.len() >= cap) }
+
+    fn accepts(&self) -> bool { self.receiver && !self.closed }
+
+    fn pop(&mut self) -> Option<T> { self.lanes.iter_mut().find_map(VecDeque::pop_front) }
+}
+
+struct Queue<T> {
+    inner:     Mutex<Inner<T>>,
+    not_empty: Condvar,
+    not_full:  Condvar,
+    clock:     Arc<dyn Clock>,
+}
+
+impl<T> Queue<T> {
+    fn lock(&self) -> MutexGuard<'_, Inner<T>> { self.inner.lock().unwrap() }
+
+    fn push(&self, mut inner: MutexGuard<'_, Inner<T>>, item: T, priority: Priority) {
+        inner.lanes[priority.lane()].push_back(item);
+        self.not_empty.notify_one();
+    }
+
+    /// Wait on `cond` until woken, or until `deadline` of the clock. Returns
+    /// `None` once deadline has passed.
+    fn wait_until<'a>(
+        &self,
+        cond: &Condvar,
+        inner: MutexGuard<'a, Inner<T>>,
+        deadline: Instant,
+    ) -> Option<MutexGuard<'a, Inner<T>>> {
+        if self.clock.now() >= deadline {
+            return None;
+        }
+        Some(match self.clock.wait_for(deadline) {
+            Some(timeout) => cond.wait_timeout(inner, timeout).unwrap().0,
+            None => cond.wait(inner).unwrap(),
+        })
+    }
+}
+
+/// Create queue of given capacity, or unbounded one for `None`. Timeouts are
+/// measured with `clock`.
+pub(crate) fn channel<T: Send + 'static>(
+    capacity: Option<usize>,
+    clock: Arc<dyn Clock>,
+) -> (Sender<T>, Receiver<T>) {
+    let queue = Arc::new(Queue {
+        inner: Mutex::new(Inner {
+            lanes: Default::default(),
+            control: VecDeque::new(),
+            capacity,
+            senders: 1,
+            receiver: true,
+            closed: false,
+        }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+        clock,
+    });
+
+    // Let waiters recheck deadlines when clock jumps
+    let weak = Arc::downgrade(&queue);
+    let wake: Arc<dyn Fn() + Send + Sync> = Arc::new(move || {
+        if let Some(queue) = weak.upgrade() {
+            let _inner = queue.lock();
+            queue.not_empty.notify_all();
+            queue.not_full.notify_all();
+        }
+    });
+    queue.clock.subscribe(Arc::downgrade(&wake));
+
+    (
+        Sender {
+            queue: queue.clone(),
+        },
+        Receiver {
+            queue,
+            _wake: wake,
+        },
+    )
+}
+
+/// Sending end, cloneable.
+pub(crate) struct Sender<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Send item, blocking while queue is full.
+    pub(crate) fn send(&self, item: T, priority: Priority) -> Result<(), ReactorError<T>> {
+        let mut inner = self.queue.lock();
+        loop {
+            if !inner.accepts() {
+                return Err(ReactorError::MachineTerminated(item));
+            }
+            if !inner.is_full() {
+                self.queue.push(inner, item, priority);
+                return Ok(());
+            }
+            inner = self.queue.not_full.wait(inner).unwrap();
+        }
+    }
+
+    /// Send item if there is room in queue, never blocks.
+    pub(crate) fn try_send(&self, item: T, priority: Priority) -> Result<(), ReactorError<T>> {
+        let inner = self.queue.lock();
+        if !inner.accepts() {
+            Err(ReactorError::MachineTerminated(item))
+        } else if inner.is_full() {
+            Err(ReactorError::QueueFull(item))
+        } else {
+            self.queue.push(inner, item, priority);
+            Ok(())
+        }
+    }
+
+    /// Send item, blocking at most `timeout` while queue is full.
+    pub(crate) fn send_timeout(
+        &self,
+        item: T,
+        priority: Priority,
+        timeout: Duration,
+    ) -> Result<(), ReactorError<T>> {
+        let deadline = self.queue.clock.now() + timeout;
+        let mut inner = self.queue.lock();
+        loop {
+            if !inner.accepts() {
+                return Err(ReactorError::MachineTerminated(item));
+            }
+            if !inner.is_full() {
+                self.queue.push(inner, item, priority);
+                return Ok(());
+            }
+            inner = match self.queue.wait_until(&self.queue.not_full, inner, deadline) {
+                Some(inner) => inner,
+                None => return Err(ReactorError::Timeout(item)),
+            };
+        }
+    }
+
+    /// Send command through the control lane, never blocks. `Drain` takes
+    /// effect at once, so no item sent after it is accepted.
+    pub(crate) fn control(&self, cmd: Control) -> Result<(), ReactorError<Control>> {
+        let mut inner = self.queue.lock();
+        if !inner.receiver {
+            return Err(ReactorError::MachineTerminated(cmd));
+        }
+        if cmd == Control::Drain {
+            inner.closed = true;
+            // Blocked senders fail now
+            self.queue.not_full.notify_all();
+        } else {
+            inner.control.push_back(cmd);
+        }
+        self.queue.not_empty.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.queue.lock().senders += 1;
+        Self {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut inner = self.queue.lock();
+        inner.senders -= 1;
+        if inner.senders == 0 {
+            self.queue.not_empty.notify_all();
+        }
+    }
+}
+
+/// Receiving end, owned by the machine thread.
+pub(crate) struct Receiver<T> {
+    queue: Arc<Queue<T>>,
+    /// Keeps clock subscription alive
+    _wake: Arc<dyn Fn() + Send + Sync>,
+}
+
+impl<T> Receiver<T> {
+    /// Wait for next command or item, at most until `deadline`. Items are
+    /// left in queue while `paused`. Fails when there is nothing to receive
+    /// and all senders are gone or queue is closed.
+    pub(crate) fn recv_deadline(
+        &self,
+        deadline: Option<Instant>,
+        paused: bool,
+    ) -> Result<Received<T>, mpsc::RecvTimeoutError> {
+        let mut inner = self.queue.lock();
+        loop {
+            if let Some(cmd) = inner.control.pop_front() {
+                return Ok(Received::Control(cmd));
+            }
+            if !paused {
+                if let Some(item) = inner.pop() {
+                    self.queue.not_full.notify_one();
+                    return Ok(Received::Item(item));
+                }
+            }
+            if inner.senders == 0 || (inner.closed && inner.len() == 0) {
+                return Err(mpsc::RecvTimeoutError::Disconnected);
+            }
+            inner = match deadline {
+                None => self.queue.not_empty.wait(inner).unwrap(),
+                Some(deadline) => {
+                    match self
+                        .queue
+                        .wait_until(&self.queue.not_empty, inner, deadline)
+                    {
+                        Some(inner) => inner,
+                        None => return Err(mpsc::RecvTimeoutError::Timeout),
+                    }
+                }
+            };
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut inner = self.queue.lock();
+        inner.receiver = false;
+        inner.lanes.iter_mut().for_each(VecDeque::clear);
+        self.queue.not_full.notify_all();
+    }
+}
@@ -0,0 +1,840 @@
+//! Running `FSM` in its own thread.
+
+use crate::{
+    builder::Factory,
+    history::{Log, Recorder},
+    queue::{self, Received},
+    sender::Envelope,
+    trace,
+    watch::{self, StateWatch},
+    Clock, Control, EventSender, Machine, PendingReply, ReactorBuilder, ReactorError, Record,
+    Reply, StepOutcome, Supervisor, FSM,
+};
+#[cfg(feature = "journal")]
+use crate::{journal::Journaling, Entry, Journal};
+#[cfg(feature = "serde")]
+use crate::{Snapshot, SnapshotRef};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::{
+    any, io, mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+/// How [`Reactor::shutdown`] stops the machine
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Shutdown {
+    /// Refuse further events, process ones already queued, then stop
+    Drain,
+    /// Stop at once, discarding queued events
+    Immediate,
+}
+
+/// What dropping a [`Reactor`] does to its machine
+///
+/// # Example
+/// ```
+/// # use pakr_fsm::*;
+/// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// # struct MyEv;
+/// # #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+/// # struct MyState;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+///
+/// static HANDLED: AtomicUsize = AtomicUsize::new(0);
+/// static SHUT_DOWN: AtomicUsize = AtomicUsize::new(0);
+///
+/// struct MyFSM;
+///
+/// impl FSM for MyFSM {
+///     type Event = MyEv;
+///     type Response = ();
+///     type State = MyState;
+///
+///     fn new() -> Self { Self }
+///
+///     fn trasnsit(&mut self, _: &MyState, _: &MyEv) -> (Option<MyState>, Option<()>) {
+///         HANDLED.fetch_add(1, Ordering::SeqCst);
+///         (Some(MyState), None)
+///     }
+///
+///     fn respond(&mut self, _: &MyState, _: &Option<MyState>, _: &()) {}
+///
+///     fn on_shutdown(&mut self, _: &MyState) -> Option<()> {
+///         SHUT_DOWN.fetch_add(1, Ordering::SeqCst);
+///         None
+///     }
+/// }
+///
+/// // Dropped machine processes all that was queued and shuts down
+/// let fsm = ReactorBuilder::<MyFSM>::new()
+///     .drop_policy(DropPolicy::Join)
+///     .spawn()
+///     .unwrap();
+/// fsm.control(Control::Pause).unwrap();
+/// for _ in 0 .. 3 {
+///     fsm.send(MyEv).unwrap();
+/// }
+/// fsm.control(Control::Resume).unwrap();
+/// drop(fsm);
+/// assert_eq!(HANDLED.load(Ordering::SeqCst), 3);
+/// assert_eq!(SHUT_DOWN.load(Ordering::SeqCst), 1);
+///
+/// // Aborted machine no longer takes events from remaining senders
+/// let fsm = ReactorBuilder::<MyFSM>::new()
+///     .drop_policy(DropPolicy::Abort)
+///     .spawn()
+///     .unwrap();
+/// let sender = fsm.get_sender();
+/// let mut watch = fsm.watch();
+/// drop(fsm);
+/// assert_eq!(watch.changed(), Err(ReactorError::MachineTerminated(())));
+/// assert_eq!(
+///     sender.send(MyEv),
+///     Err(ReactorError::MachineTerminated(MyEv))
+/// );
+/// ```
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub enum DropPolicy {
+    /// Shut machine down with [`Shutdown::Drain`] and wait for it
+    Join,
+    /// Leave machine running for as long as there are senders to feed it
+    #[default]
+    Detach,
+    /// Stop machine with [`Shutdown::Immediate`], without waiting for it
+    Abort,
+}
+
+/// Reactor is `FSM` handle to interact and monitor
+pub struct Reactor<F: FSM> {
+    /// Taken by `join`
+    reactor: Option<thread::JoinHandle<Option<F::Response>>>,
+    chan:    EventSender<F>,
+    state:   StateWatch<F::State>,
+    on_drop: DropPolicy,
+    history: Option<Log<F>>,
+    name:    Option<String>,
+}
+
+impl<F: FSM + 'static> Reactor<F> {
+    /// Create new `Reactor`.
+    ///
+    /// Spawns associated `FSM` in separate thread, itializes it by calling its
+    /// `new` and setting state to `default`. Event queue is unbounded.
+    pub fn new() -> Self { Self::build(ReactorBuilder::new()) }
+
+    /// Create new `Reactor` with event queue holding at most `capacity` events.
+    ///
+    /// When queue is full, `send` blocks until machine catches up. Panics if
+    /// `capacity` is 0, as such queue could never accept any event.
+    ///
+    /// # Example
+    /// ```
+    /// # use pakr_fsm::*;
+    /// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    /// # enum MyEv { Data(u32), Abort }
+    /// # #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    /// # struct MyState;
+    /// # #[derive(Default)]
+    /// # struct MyFSM { seen: Vec<MyEv> }
+    /// # impl FSM for MyFSM {
+    /// #     type Event = MyEv;
+    /// #     type Response = Vec<MyEv>;
+    /// #     type State = MyState;
+    /// #     fn new() -> Self { Self::default() }
+    /// #     fn trasnsit(&mut self, _: &MyState, ev: &MyEv) -> (Option<MyState>, Option<Vec<MyEv>>) {
+    /// #         self.seen.push(*ev);
+    /// #         match ev {
+    /// #             MyEv::Abort => (None, Some(self.seen.clone())),
+    /// #             MyEv::Data(_) => (Some(MyState), None),
+    /// #         }
+    /// #     }
+    /// #     fn respond(&mut self, _: &MyState, _: &Option<MyState>, _: &Vec<MyEv>) {}
+    /// # }
+    /// use std::{thread, time::Duration};
+    ///
+    /// let fsm = Reactor::<MyFSM>::bounded(1);
+    /// let sender = fsm.get_sender();
+    /// let wait = Duration::from_millis(10);
+    ///
+    /// // Nothing is processed while paused, so the single slot stays taken
+    /// fsm.control(Control::Pause).unwrap();
+    /// assert_eq!(fsm.try_send(MyEv::Data(1)), Ok(()));
+    /// assert_eq!(fsm.try_send(MyEv::Data(2)), Err(ReactorError::QueueFull(MyEv::Data(2))));
+    /// assert_eq!(fsm.send_timeout(MyEv::Data(2), wait), Err(ReactorError::Timeout(MyEv::Data(2))));
+    /// assert_eq!(sender.try_send(MyEv::Data(2)), Err(ReactorError::QueueFull(MyEv::Data(2))));
+    /// assert_eq!(
+    ///     sender.send_timeout(MyEv::Data(2), wait),
+    ///     Err(ReactorError::Timeout(MyEv::Data(2)))
+    /// );
+    ///
+    /// // Blocked sender goes on once machine catches up
+    /// let blocked = thread::spawn(move || sender.send(MyEv::Data(3)));
+    /// fsm.control(Control::Resume).unwrap();
+    /// assert_eq!(blocked.join().unwrap(), Ok(()));
+    /// fsm.send(MyEv::Abort).unwrap();
+    /// assert_eq!(fsm.join(), Ok(Some(vec![MyEv::Data(1), MyEv::Data(3), MyEv::Abort])));
+    /// ```
+    pub fn bounded(capacity: usize) -> Self {
+        Self::build(ReactorBuilder::new().queue_capacity(capacity))
+    }
+
+    /// Create new `Reactor` measuring time with `clock`, e.g. a
+    /// [`ManualClock`](crate::ManualClock) in tests. Event queue is unbounded.
+    pub fn with_clock(clock: impl Clock + 'static) -> Self {
+        Self::build(ReactorBuilder::new().clock(clock))
+    }
+
+    /// Create new `Reactor` recording its last `capacity` steps, available
+    /// from `history` and `join_with_history`. Event queue is unbounded.
+    ///
+    /// # Example
+    /// ```
+    /// # use pakr_fsm::*;
+    /// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    /// # enum MyEv { E1, E2 }
+    /// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    /// # enum MyState { S1, S2 }
+    /// # impl Default for MyState {
+    /// #     fn default() -> Self { Self::S1 }
+    /// # }
+    /// # struct MyFSM;
+    /// # impl FSM for MyFSM {
+    /// #     type Event = MyEv;
+    /// #     type Response = &'static str;
+    /// #     type State = MyState;
+    /// #     fn new() -> Self { Self {} }
+    /// #     fn trasnsit(
+    /// #         &mut self,
+    /// #         old_state: &Self::State,
+    /// #         ev: &Self::Event,
+    /// #     ) -> (Option<Self::State>, Option<Self::Response>) {
+    /// #         match (old_state, ev) {
+    /// #             (MyState::S1, MyEv::E1) => (None, Some("Quitting")),
+    /// #             (MyState::S1, MyEv::E2) => (Some(MyState::S2), None),
+    /// #             (MyState::S2, MyEv::E1) => (Some(MyState::S1), Some("S2@E1->S1")),
+    /// #             (MyState::S2, MyEv::E2) => (Some(MyState::S2), Some("S2@E2->S2")),
+    /// #         }
+    /// #     }
+    /// #     fn respond(&mut self, _: &MyState, _: &Option<MyState>, _: &&'static str) {}
+    /// # }
+    /// let fsm = Reactor::<MyFSM>::with_history(2);
+    /// fsm.ask(MyEv::E2).unwrap();
+    /// assert_eq!(fsm.history().len(), 1);
+    ///
+    /// fsm.send(MyEv::E2).unwrap();
+    /// fsm.send(MyEv::E1).unwrap();
+    /// fsm.send(MyEv::E1).unwrap();
+    /// let (result, history) = fsm.join_with_history();
+    /// assert_eq!(result, Ok(Some("Quitting")));
+    ///
+    /// let steps: Vec<_> = history
+    ///     .iter()
+    ///     .map(|rec| (rec.from, rec.event, rec.to, rec.responded))
+    ///     .collect();
+    /// assert_eq!(
+    ///     steps,
+    ///     [
+    ///         (MyState::S2, MyEv::E1, Some(MyState::S1), true),
+    ///         (MyState::S1, MyEv::E1, None, true)
+    ///     ]
+    /// );
+    /// ```
+    pub fn with_history(capacity: usize) -> Self
+    where
+        F::Event: Clone,
+    {
+        Self::build(ReactorBuilder::new().history(capacity))
+    }
+
+    /// Create new `Reactor`, resuming machine from `snapshot`, e.g. one
+    /// taken by `Reactor::snapshot`. State of snapshot is not entered again, so
+    /// its `on_enter` is not called. Event queue is unbounded.
+    ///
+    /// See [`Snapshot`] for an example.
+    #[cfg(feature = "serde")]
+    pub fn restore(snapshot: Snapshot<F>) -> Self
+    where
+        F: Send,
+    {
+        Self::build(ReactorBuilder::new().restore(snapshot))
+    }
+
+    /// Create new `Reactor`, rebuilding machine from `journal` and then
+    /// recording to it every event it processes. Event queue is unbounded.
+    /// Fails if journal could not be read.
+    ///
+    /// Machine that terminated while recorded ends again at once. See
+    /// [`Journal`] for an example.
+    #[cfg(feature = "journal")]
+    pub fn with_journal(journal: impl Journal<F> + 'static) -> io::Result<Self>
+    where
+        F: Send,
+    {
+        ReactorBuilder::new().journal(journal)?.spawn()
+    }
+
+    /// Create new `Reactor` running already built `fsm`, starting in `state`.
+    /// Event queue is unbounded.
+    ///
+    /// Lets machine be given constructor arguments, e.g. configuration. For
+    /// machines that also have to be rebuilt after a panic see
+    /// [`ReactorBuilder::factory`].
+    pub fn from_parts(fsm: F, state: F::State) -> Self
+    where
+        F: Send,
+    {
+        Self::build(ReactorBuilder::new().fsm(fsm).initial_state(state))
+    }
+
+    /// Spawn `Reactor` configured by `builder`, panicking if thread could not
+    /// be created, just like `thread::spawn` does.
+    fn build(builder: ReactorBuilder<F>) -> Self {
+        builder.spawn().expect("failed to spawn thread")
+    }
+
+    pub(crate) fn spawn(builder: ReactorBuilder<F>) -> io::Result<Self> {
+        let ReactorBuilder {
+            name,
+            stack_size,
+            capacity,
+            initial,
+            clock,
+            recorder,
+            mut supervisor,
+            on_drop,
+            fsm,
+            factory,
+            resume,
+            #[cfg(feature = "journal")]
+            journal,
+        } = builder;
+
+        let (tx, rx) = queue::channel::<Envelope<F>>(capacity, clock.clone());
+        let publisher = watch::Publisher::new(initial.clone(), clock.clone());
+        let state = publisher.watch();
+        let history = recorder.as_ref().map(Recorder::log);
+        if let Some(supervisor) = &mut supervisor {
+            supervisor.start_in(initial.clone());
+            supervisor.name(name.clone());
+        }
+
+        let mut thread = thread::Builder::new();
+        if let Some(name) = &name {
+            thread = thread.name(name.clone());
+        }
+        if let Some(size) = stack_size {
+            thread = thread.stack_size(size);
+        }
+        let runner = Runner {
+            rx,
+            publisher,
+            supervisor,
+            clock,
+            recorder,
+            initial,
+            fsm,
+            factory: factory.unwrap_or_else(|| Box::new(F::new)),
+            resume,
+            #[cfg(feature = "journal")]
+            journal,
+            name: name
+                .clone()
+                .unwrap_or_else(|| any::type_name::<F>().to_string()),
+        };
+        let t = thread.spawn(move || runner.run())?;
+
+        Ok(Self {
+            reactor: Some(t),
+            chan: EventSender::new(tx),
+            state,
+            on_drop,
+            history,
+            name,
+        })
+    }
+
+    /// Set what dropping this `Reactor` does to its machine. Default is to
+    /// detach it.
+    pub fn drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.on_drop = policy;
+        self
+    }
+
+    /// Waits for `FSM` to complete.
+    ///
+    /// Returns response of the last transition. Panic message of named machine
+    /// is prefixed with its name.
+    pub fn join(mut self) -> Result<Option<<F as FSM>::Response>, ReactorError<<F as FSM>::Event>> {
+        let name = self.name.take();
+        self.reactor.take().unwrap().join().map_err(|payload| {
+            match (ReactorError::panicked(&*payload), name) {
+                (ReactorError::MachinePanicked(msg), Some(name)) => {
+                    ReactorError::MachinePanicked(format!("{}: {}", name, msg))
+                }
+                (err, _) => err,
+            }
+        })
+    }
+
+    /// Same as `join`, also returning recorded history of the machine, oldest
+    /// step first. History is empty unless enabled with `with_history`.
+    #[allow(clippy::type_complexity)]
+    pub fn join_with_history(
+        mut self,
+    ) -> (
+        Result<Option<<F as FSM>::Response>, ReactorError<<F as FSM>::Event>>,
+        Vec<Record<F>>,
+    ) {
+        let history = self.history.take();
+        let result = self.join();
+        let records = match history {
+            Some(log) => mem::take(&mut *log.lock().unwrap()).into(),
+            None => Vec::new(),
+        };
+        (result, records)
+    }
+
+    /// Stop `FSM` from the outside and wait for it.
+    ///
+    /// Machine that did not terminate by itself meanwhile is given the last
+    /// word by [`FSM::on_shutdown`], returned here as the final response.
+    /// Draining resumes paused machine.
+    ///
+    /// # Example
+    /// ```
+    /// # use pakr_fsm::*;
+    /// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    /// # struct Add(u32);
+    /// # #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    /// # struct MyState;
+    /// # #[derive(Default)]
+    /// # struct Sum(u32);
+    /// # impl FSM for Sum {
+    /// #     type Event = Add;
+    /// #     type Response = u32;
+    /// #     type State = MyState;
+    /// #     fn new() -> Self { Self::default() }
+    /// #     fn trasnsit(&mut self, _: &MyState, ev: &Add) -> (Option<MyState>, Option<u32>) {
+    /// #         self.0 += ev.0;
+    /// #         (Some(MyState), None)
+    /// #     }
+    /// #     fn respond(&mut self, _: &MyState, _: &Option<MyState>, _: &u32) {}
+    /// #     fn on_shutdown(&mut self, _: &MyState) -> Option<u32> { Some(self.0) }
+    /// # }
+    /// let fsm = Reactor::<Sum>::new();
+    /// let sender = fsm.get_sender();
+    /// fsm.control(Control::Pause).unwrap();
+    /// for n in 1 ..= 4 {
+    ///     sender.send(Add(n)).unwrap();
+    /// }
+    /// assert_eq!(fsm.shutdown(Shutdown::Drain), Ok(Some(10)));
+    /// assert!(sender.send(Add(5)).is_err());
+    ///
+    /// let fsm = Reactor::<Sum>::new();
+    /// fsm.control(Control::Pause).unwrap();
+    /// fsm.send(Add(1)).unwrap();
+    /// assert_eq!(fsm.shutdown(Shutdown::Immediate), Ok(Some(0)));
+    /// ```
+    pub fn shutdown(
+        self,
+        mode: Shutdown,
+    ) -> Result<Option<<F as FSM>::Response>, ReactorError<<F as FSM>::Event>> {
+        self.stop(mode);
+        self.join()
+    }
+
+    /// Send event to `FSM`, blocking while queue is full
+    pub fn send(&self, ev: <F as FSM>::Event) -> Result<(), ReactorError<<F as FSM>::Event>> {
+        self.chan.send(ev)
+    }
+
+    /// Send event to `FSM` if there is room in queue
+    pub fn try_send(&self, ev: <F as FSM>::Event) -> Result<(), ReactorError<<F as FSM>::Event>> {
+        self.chan.try_send(ev)
+    }
+
+    /// Send event to `FSM`, waiting at most `timeout` for room in queue
+    pub fn send_timeout(
+        &self,
+        ev: <F as FSM>::Event,
+        timeout: Duration,
+    ) -> Result<(), ReactorError<<F as FSM>::Event>> {
+        self.chan.send_timeout(ev, timeout)
+    }
+
+    /// Send event to `FSM` and wait until it is processed, returning response
+    /// of its transition. See [`EventSender::ask`].
+    pub fn ask(
+        &self,
+        ev: <F as FSM>::Event,
+    ) -> Result<Option<<F as FSM>::Response>, ReactorError<<F as FSM>::Event>> {
+        self.chan.ask(ev)
+    }
+
+    /// Send event to `FSM` without waiting for it to be processed. See
+    /// [`EventSender::request`].
+    pub fn request(
+        &self,
+        ev: <F as FSM>::Event,
+    ) -> Result<PendingReply<F>, ReactorError<<F as FSM>::Event>> {
+        self.chan.request(ev)
+    }
+
+    /// Send command to the `Reactor` itself, ahead of all queued events.
+    ///
+    /// # Example
+    /// ```
+    /// # use pakr_fsm::*;
+    /// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    /// # enum MyEv { Data(u32), Abort }
+    /// # #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    /// # struct MyState;
+    /// # #[derive(Default)]
+    /// # struct MyFSM { seen: Vec<MyEv> }
+    /// # impl FSM for MyFSM {
+    /// #     type Event = MyEv;
+    /// #     type Response = Vec<MyEv>;
+    /// #     type State = MyState;
+    /// #     fn new() -> Self { Self::default() }
+    /// #     fn trasnsit(&mut self, _: &MyState, ev: &MyEv) -> (Option<MyState>, Option<Vec<MyEv>>) {
+    /// #         self.seen.push(*ev);
+    /// #         match ev {
+    /// #             MyEv::Abort => (None, Some(self.seen.clone())),
+    /// #             MyEv::Data(_) => (Some(MyState), None),
+    /// #         }
+    /// #     }
+    /// #     fn respond(&mut self, _: &MyState, _: &Option<MyState>, _: &Vec<MyEv>) {}
+    /// #     fn priority(ev: &MyEv) -> Priority {
+    /// #         match ev {
+    /// #             MyEv::Abort => Priority::High,
+    /// #             MyEv::Data(_) => Priority::Normal,
+    /// #         }
+    /// #     }
+    /// # }
+    /// let fsm = Reactor::<MyFSM>::new();
+    ///
+    /// // Nothing is processed while paused, so urgent event overtakes the rest
+    /// fsm.control(Control::Pause).unwrap();
+    /// fsm.send(MyEv::Data(1)).unwrap();
+    /// fsm.send(MyEv::Data(2)).unwrap();
+    /// fsm.send(MyEv::Abort).unwrap();
+    /// fsm.control(Control::Resume).unwrap();
+    /// assert_eq!(fsm.join(), Ok(Some(vec![MyEv::Abort])));
+    ///
+    /// // Draining machine processes what was queued, but nothing more
+    /// let fsm = Reactor::<MyFSM>::new();
+    /// fsm.send(MyEv::Data(1)).unwrap();
+    /// fsm.control(Control::Drain).unwrap();
+    /// assert!(fsm.send(MyEv::Data(2)).is_err());
+    /// assert_eq!(fsm.join(), Ok(None));
+    /// ```
+    pub fn control(&self, cmd: Control) -> Result<(), ReactorError<Control>> {
+        self.chan.control(cmd)
+    }
+
+    /// Serialize `FSM` and its state, once events sent before are processed.
+    ///
+    /// Request is queued behind events of all priorities, so events of higher
+    /// than `Low` priority sent meanwhile may be processed before it too.
+    /// `serialize` runs in the machine thread, between events, and its result
+    /// is returned. Waits while machine is paused. Serialized [`SnapshotRef`]
+    /// deserializes into [`Snapshot`], to be given to `restore`.
+    #[cfg(feature = "serde")]
+    pub fn snapshot<T: Send + 'static>(
+        &self,
+        serialize: impl FnOnce(SnapshotRef<'_, F>) -> T + Send + 'static,
+    ) -> Result<T, ReactorError<()>>
+    where
+        F: Serialize,
+        F::State: Serialize,
+    {
+        let (tx, rx) = mpsc::channel();
+        self.chan.inspect(move |machine| {
+            // Asking party may have already given up
+            let _ = tx.send(serialize(machine.snapshot()));
+        })?;
+        rx.recv().map_err(|_| ReactorError::NoReply)
+    }
+
+    /// Clone `send` endpoint of `FSM` channel to be used in other places.
+    pub fn get_sender(&self) -> EventSender<F> { self.chan.clone() }
+
+    /// Name given with [`ReactorBuilder::name`]
+    pub fn name(&self) -> Option<&str> { self.name.as_deref() }
+
+    /// Current state of `FSM`. After machine ended, it is the last state it was
+    /// in.
+    pub fn state(&self) -> F::State { self.state.get() }
+
+    /// Time `FSM` spent in current state so far, measured by its clock.
+    pub fn time_in_state(&self) -> Duration { self.state.time_in_state() }
+
+    /// Steps recorded so far, oldest first. Empty unless enabled with
+    /// `with_history`.
+    pub fn history(&self) -> Vec<Record<F>>
+    where
+        F::Event: Clone,
+    {
+        match &self.history {
+            Some(log) => log.lock().unwrap().iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Subscribe to state changes of `FSM`.
+    pub fn watch(&self) -> StateWatch<F::State> { self.state.subscribe() }
+}
+
+impl<F: FSM + 'static> Default for Reactor<F> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<F: FSM> Reactor<F> {
+    /// Request machine to stop. Machine that already ended is fine.
+    fn stop(&self, mode: Shutdown) {
+        let _ = match mode {
+            Shutdown::Drain => {
+                self.chan
+                    .control(Control::Resume)
+                    .and_then(|_| self.chan.control(Control::Drain))
+            }
+            Shutdown::Immediate => self.chan.control(Control::Stop),
+        };
+    }
+}
+
+impl<F: FSM> Drop for Reactor<F> {
+    fn drop(&mut self) {
+        let handle = match self.reactor.take() {
+            Some(handle) => handle,
+            None => return,
+        };
+        match self.on_drop {
+            DropPolicy::Join => {
+                self.stop(Shutdown::Drain);
+                // Panic of the machine is not propagated into a destructor
+                let _ = handle.join();
+            }
+            DropPolicy::Detach => {}
+            DropPolicy::Abort => self.stop(Shutdown::Immediate),
+        }
+    }
+}
+
+/// Body of the machine thread
+struct Runner<F: FSM> {
+    rx:         queue::Receiver<Envelope<F>>,
+    publisher:  watch::Publisher<F::State>,
+    supervisor: Option<Supervisor<F>>,
+    clock:      Arc<dyn Clock>,
+    recorder:   Option<Recorder<F>>,
+    initial:    F::State,
+    /// Machine to start with instead of a new one
+    fsm:        Option<Box<dyn FnOnce() -> F + Send>>,
+    /// Builds new machines
+    factory:    Factory<F>,
+    /// Initial state was already entered before
+    resume:     bool,
+    #[cfg(feature = "journal")]
+    journal:    Option<Journaling<F>>,
+    /// Name machine is reported under
+    name:       String,
+}
+
+impl<F: FSM + 'static> Runner<F> {
+    fn run(mut self) -> Option<F::Response> {
+        let mut machine = self.start();
+        if machine.is_terminated() {
+            return None;
+        }
+        let mut paused = false;
+
+        loop {
+            // Replayed events and due timers go first, then queue is waited on
+            // until the next timer
+            let pending = match paused {
+                true => None,
+                false => {
+                    machine.take_replayed_asked().or_else(|| {
+                        let ev = machine.take_expired()?;
+                        #[cfg(feature = "journal")]
+                        self.record(Entry::Timer(&ev));
+                        Some((ev, None))
+                    })
+                }
+            };
+            let (ev, reply_to) = match pending {
+                Some(pending) => pending,
+                None => {
+                    let deadline = if paused {
+                        None
+                    } else {
+                        machine.next_deadline()
+                    };
+                    match self.rx.recv_deadline(deadline, paused) {
+                        Ok(Received::Item(Envelope::Event(ev))) => {
+                            #[cfg(feature = "journal")]
+                            self.record(Entry::Event(&ev));
+                            (ev, None)
+                        }
+                        Ok(Received::Item(Envelope::Ask(ev, reply_to))) => {
+                            #[cfg(feature = "journal")]
+                            self.record(Entry::Event(&ev));
+                            (ev, Some(reply_to))
+                        }
+                        #[cfg(feature = "serde")]
+                        Ok(Received::Item(Envelope::Inspect(inspect))) => {
+                            inspect(&machine);
+                            continue;
+                        }
+                        Ok(Received::Control(cmd)) => {
+                            match cmd {
+                                Control::Pause => paused = true,
+                                Control::Resume => paused = false,
+                                // Handled by the queue itself
+                                Control::Drain => {}
+                                Control::Stop => return machine.shutdown(),
+                            }
+                            continue;
+                        }
+                        Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                        Err(mpsc::RecvTimeoutError::Disconnected) => return machine.shutdown(),
+                    }
+                }
+            };
+
+            let asked = reply_to.is_some();
+            machine.set_reply_to(reply_to);
+            let publisher = &self.publisher;
+            let recorder = self.recorder.as_ref();
+            let name = &self.name;
+            // Kept outside of the step, to be recorded even if it panics
+            let mut record = recorder.map(|rec| rec.begin(self.clock.now(), machine.state(), &ev));
+            let step = |machine: &mut Machine<F>, record: &mut Option<Record<F>>| {
+                let traced = trace::begin(machine.state(), &ev);
+                let (state, response) = match machine.step(ev) {
+                    StepOutcome::Terminated(response) => (None, response),
+                    StepOutcome::Running {
+                        state,
+                        response,
+                    } => {
+                        publisher.publish(state);
+                        (Some(state), response)
+                    }
+                };
+                if let (Some(recorder), Some(record)) = (recorder, record.take()) {
+                    recorder.commit(record, state, response.is_some());
+                }
+                if let Some(traced) = traced {
+                    trace::end(name, traced, state, response.is_some());
+                }
+                (if asked { state.cloned() } else { None }, response)
+            };
+
+            let outcome = panic::catch_unwind(AssertUnwindSafe(|| step(&mut machine, &mut record)));
+            let (state, response) = match outcome {
+                Ok(outcome) => outcome,
+                Err(payload) => {
+                    if let (Some(recorder), Some(record)) = (recorder, record) {
+                        recorder.commit_panicked(record);
+                    }
+                    let supervisor = match &mut self.supervisor {
+                        Some(supervisor) => supervisor,
+                        None => panic::resume_unwind(payload),
+                    };
+                    machine = supervisor
+                        .recover(&mut machine, payload, &self.clock, &mut self.factory)
+                        .unwrap_or_else(|payload| panic::resume_unwind(payload));
+                    self.publisher.publish(machine.state());
+                    // Recorded events no longer lead to this machine
+                    #[cfg(feature = "journal")]
+                    self.compact(&machine, true);
+                    continue;
+                }
+            };
+
+            // Deferred event keeps its asking party waiting
+            let response = match machine.take_reply_to() {
+                Some(reply_to) => {
+                    // Asking party may have already given up
+                    let _ = reply_to.send(Reply {
+                        state,
+                        response,
+                    });
+                    None
+                }
+                None => response,
+            };
+
+            #[cfg(feature = "journal")]
+            self.compact(&machine, false);
+            if machine.is_terminated() {
+                return response;
+            }
+        }
+    }
+
+    /// Record event about to be processed, if journal is kept
+    #[cfg(feature = "journal")]
+    fn record(&mut self, entry: Entry<&F::Event>) {
+        if let Some(journal) = &mut self.journal {
+            journal.record(entry);
+        }
+    }
+
+    /// Compact journal, if kept and due or `force`d
+    #[cfg(feature = "journal")]
+    fn compact(&mut self, machine: &Machine<F>, force: bool) {
+        if let Some(journal) = &mut self.journal {
+            journal.compact(machine, force);
+        }
+    }
+
+    fn start(&mut self) -> Machine<F> {
+        let clock = &self.clock;
+        let initial = &self.initial;
+        let fsm = self.fsm.take();
+        let factory = &mut self.factory;
+        let resume = self.resume;
+        #[cfg(feature = "journal")]
+        let journal = &mut self.journal;
+        let start = move || {
+            let fsm = match fsm {
+                Some(fsm) => fsm(),
+                None => factory(),
+            };
+            let machine = match resume {
+                true => Machine::resume(fsm, initial.clone(), clock.clone()),
+                false => Machine::with_clock(fsm, initial.clone(), clock.clone()),
+            };
+            #[cfg(feature = "journal")]
+            let machine = match journal {
+                Some(journal) => journal.replay(machine),
+                None => machine,
+            };
+            machine
+        };
+        match &mut self.supervisor {
+            None => start(),
+            Some(supervisor) => {
+                match panic::catch_unwind(AssertUnwindSafe(start)) {
+                    Ok(machine) => machine,
+                    Err(payload) => {
+                        let machine = supervisor
+                            .recover_start(payload, clock, &mut self.factory)
+                            .unwrap_or_else(|payload| panic::resume_unwind(payload));
+                        #[cfg(feature = "journal")]
+                        if let Some(journal) = &mut self.journal {
+                            journal.compact(&machine, true);
+                        }
+                        machine
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! Sending events to [`Reactor`](crate::Reactor), with or without waiting for
+//! the outcome.
+
+use crate::{queue, Control, ReactorError, FSM};
+#[cfg(feature = "serde")]
+use crate::{Machine, Priority};
+use std::{sync::mpsc, time::Duration};
+
+/// Outcome of a single event, delivered back to the asking party
+pub struct Reply<F: FSM> {
+    /// State after the transition, `None` if machine terminated
+    pub state:    Option<F::State>,
+    /// Response of the transition
+    pub response: Option<F::Response>,
+}
+
+/// Channel [`Reply`] is delivered on
+pub(crate) type ReplyTo<F> = mpsc::Sender<Reply<F>>;
+
+/// Item of the event queue
+pub(crate) enum Envelope<F: FSM> {
+    Event(F::Event),
+    Ask(F::Event, ReplyTo<F>),
+    /// Look at the machine between events
+    #[cfg(feature = "serde")]
+    Inspect(Inspect<F>),
+}
+
+/// Look at the machine, from its own thread
+#[cfg(feature = "serde")]
+pub(crate) type Inspect<F> = Box<dyn FnOnce(&Machine<F>) + Send>;
+
+impl<F: FSM> Envelope<F> {
+    /// Event carried, dropping eventual reply channel
+    pub(crate) fn into_event(self) -> F::Event {
+        match self {
+            Envelope::Event(ev) | Envelope::Ask(ev, _) => ev,
+            #[cfg(feature = "serde")]
+            Envelope::Inspect(_) => unreachable!("inspection is not an event"),
+        }
+    }
+}
+
+/// Cloneable `send` endpoint of [`Reactor`](crate::Reactor) queue.
+///
+/// For unbounded queue all send variants succeed immediately, as long as
+/// machine is alive. Events are queued with priority given by
+/// [`FSM::priority`].
+pub struct EventSender<F: FSM> {
+    chan: queue::Sender<Envelope<F>>,
+}
+
+impl<F: FSM> EventSender<F> {
+    pub(crate) fn new(chan: queue::Sender<Envelope<F>>) -> Self {
+        Self {
+            chan,
+        }
+    }
+
+    /// Send event to `FSM`, blocking while queue is full
+    pub fn send(&self, ev: F::Event) -> Result<(), ReactorError<F::Event>> {
+        let priority = F::priority(&ev);
+        self.chan
+            .send(Envelope::Event(ev), priority)
+            .map_err(|err| err.map(Envelope::into_event))
+    }
+
+    /// Send event to `FSM` if there is room in queue
+    pub fn try_send(&self, ev: F::Event) -> Result<(), ReactorError<F::Event>> {
+        let priority = F::priority(&ev);
+        self.chan
+            .try_send(Envelope::Event(ev), priority)
+            .map_err(|err| err.map(Envelope::into_event))
+    }
+
+    /// Send event to `FSM`, waiting at most `timeout` for room in queue
+    pub fn send_timeout(
+        &self,
+        ev: F::Event,
+        timeout: Duration,
+    ) -> Result<(), ReactorError<F::Event>> {
+        let priority = F::priority(&ev);
+        self.chan
+            .send_timeout(Envelope::Event(ev), priority, timeout)
+            .map_err(|err| err.map(Envelope::into_event))
+    }
+
+    /// Send event to `FSM` and wait until it is processed, returning response
+    /// of its transition.
+    ///
+    /// Response delivered this way is still passed to `FSM::respond` first.
+    /// If event terminates machine, its response goes here and not to
+    /// `Reactor::join`.
+    pub fn ask(&self, ev: F::Event) -> Result<Option<F::Response>, ReactorError<F::Event>> {
+        self.request(ev)?.wait().map(|reply| reply.response)
+    }
+
+    /// Send event to `FSM` without waiting for it to be processed. Returned
+    /// handle delivers [`Reply`] once it is.
+    pub fn request(&self, ev: F::Event) -> Result<PendingReply<F>, ReactorError<F::Event>> {
+        let (tx, rx) = mpsc::channel();
+        let priority = F::priority(&ev);
+        self.chan
+            .send(Envelope::Ask(ev, tx), priority)
+            .map_err(|err| err.map(Envelope::into_event))?;
+        Ok(PendingReply {
+            rx,
+        })
+    }
+
+    /// Run `inspect` on the machine once events sent before are processed.
+    /// It waits behind events of every priority, so also after events of
+    /// higher priority sent meanwhile.
+    #[cfg(feature = "serde")]
+    pub(crate) fn inspect(
+        &self,
+        inspect: impl FnOnce(&Machine<F>) + Send + 'static,
+    ) -> Result<(), ReactorError<()>> {
+        self.chan
+            .send(Envelope::Inspect(Box::new(inspect)), Priority::Low)
+            .map_err(|err| err.map(|_| ()))
+    }
+
+    /// Send command to the `Reactor` itself, ahead of all queued events
+    pub fn control(&self, cmd: Control) -> Result<(), ReactorError<Control>> {
+        self.chan.control(cmd)
+    }
+}
+
+impl<F: FSM> Clone for EventSender<F> {
+    fn clone(&self) -> Self {
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+/// Handle to [`Reply`] of an event sent with [`EventSender::request`]
+pub struct PendingReply<F: FSM> {
+    rx: mpsc::Receiver<Reply<F>>,
+}
+
+impl<F: FSM> PendingReply<F> {
+    /// Wait for the reply
+    pub fn wait(self) -> Result<Reply<F>, ReactorError<F::Event>> {
+        self.rx.recv().map_err(|_| ReactorError::NoReply)
+    }
+
+    /// Check for the reply without blocking. `None` means event is not
+    /// processed yet.
+    pub fn try_wait(&self) -> Result<Option<Reply<F>>, ReactorError<F::Event>> {
+        match self.rx.try_recv() {
+            Ok(reply) => Ok(Some(reply)),
+            Err(mpsc::TryRecvError::Empty) => Ok(None),
+            Err(mpsc::TryRecvError::Disconnected) => Err(ReactorError::NoReply),
+        }
+    }
+
+    /// Wait at most `timeout` for the reply. `None` means event is not
+    /// processed yet.
+    ///
+    /// Unlike [`EventSender::send_timeout`], `timeout` is real time, never
+    /// measured by [`Clock`](crate::Clock) of the machine, so it elapses even
+    /// while [`ManualClock`](crate::ManualClock) stands still.
+    pub fn wait_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<Option<Reply<F>>, ReactorError<F::Event>> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(reply) => Ok(Some(reply)),
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(ReactorError::NoReply),
+        }
+    }
+}
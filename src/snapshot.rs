@@ -0,0 +1,90 @@
+//! Persisting running machines with `serde`.
+
+use crate::FSM;
+use serde::{Deserialize, Serialize};
+
+/// `FSM` together with its state, to resume machine from with
+/// [`Reactor::restore`](crate::Reactor::restore) or
+/// [`Machine::restore`](crate::Machine::restore).
+///
+/// Serialized form is the same as of [`SnapshotRef`], so it is what
+/// [`Reactor::snapshot`](crate::Reactor::snapshot) produces. Pending timers and
+/// deferred events are not part of a snapshot, neither is anything `FSM`
+/// skips when serialized, like its [`Context`](crate::Context).
+///
+/// # Example
+/// ```
+/// # use pakr_fsm::*;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// struct Add(u32);
+///
+/// #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+/// enum Parity {
+///     #[default]
+///     Even,
+///     Odd,
+/// }
+///
+/// #[derive(Default, Serialize, Deserialize)]
+/// struct Sum(u32);
+///
+/// impl FSM for Sum {
+///     type Event = Add;
+///     type Response = u32;
+///     type State = Parity;
+///
+///     fn new() -> Self { Self::default() }
+///
+///     fn trasnsit(&mut self, _: &Parity, ev: &Add) -> (Option<Parity>, Option<u32>) {
+///         self.0 += ev.0;
+///         match self.0 % 2 {
+///             0 => (Some(Parity::Even), Some(self.0)),
+///             _ => (Some(Parity::Odd), Some(self.0)),
+///         }
+///     }
+///
+///     fn respond(&mut self, _: &Parity, _: &Option<Parity>, _: &u32) {}
+/// }
+///
+/// let fsm = Reactor::<Sum>::new();
+/// fsm.send(Add(2)).unwrap();
+/// fsm.send(Add(3)).unwrap();
+/// let saved = fsm
+///     .snapshot(|snap| serde_json::to_string(&snap))
+///     .unwrap()
+///     .unwrap();
+/// assert_eq!(saved, r#"{"fsm":5,"state":"Odd"}"#);
+///
+/// let snap: Snapshot<Sum> = serde_json::from_str(&saved).unwrap();
+/// let fsm = Reactor::restore(snap);
+/// assert_eq!(fsm.state(), Parity::Odd);
+/// assert_eq!(fsm.ask(Add(1)), Ok(Some(6)));
+/// ```
+#[derive(Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "F: Serialize, F::State: Serialize",
+    deserialize = "F: Deserialize<'de>, F::State: Deserialize<'de>"
+))]
+pub struct Snapshot<F: FSM> {
+    /// The machine
+    pub fsm:   F,
+    /// State it was in
+    pub state: F::State,
+}
+
+/// Borrowed [`Snapshot`], as given to be serialized by
+/// [`Reactor::snapshot`](crate::Reactor::snapshot) and
+/// [`Machine::snapshot`](crate::Machine::snapshot).
+#[derive(Serialize)]
+#[serde(
+    rename = "Snapshot",
+    bound(serialize = "F: Serialize, F::State: Serialize")
+)]
+pub struct SnapshotRef<'a, F: FSM> {
+    /// The machine
+    pub fsm:   &'a F,
+    /// State it is in
+    pub state: &'a F::State,
+}
@@ -0,0 +1,256 @@
+//! Restarting machines that panicked.
+
+use crate::{builder::Factory, error, Clock, Machine, Reactor, ReactorBuilder, FSM};
+use std::{
+    any::Any,
+    collections::VecDeque,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Strategy applied by [`Supervisor`] when machine panics
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Restart {
+    /// Start over with new `FSM` and initial state
+    Fresh,
+    /// Start over with new `FSM` in the state machine was in when it
+    /// panicked
+    LastState,
+    /// Never restart, give up at the first panic
+    Escalate,
+}
+
+type PanicHook<F> = Box<dyn FnMut(&PanicReport<F>) + Send>;
+type EscalateHook<F> = Box<dyn FnOnce(PanicReport<F>) + Send>;
+
+/// Details of a single panic of supervised machine
+pub struct PanicReport<F: FSM> {
+    /// Panic message
+    pub message:  String,
+    /// Event being processed, `None` if machine panicked while (re)starting
+    pub event:    Option<F::Event>,
+    /// State machine was in
+    pub state:    F::State,
+    /// Number of restarts done so far
+    pub restarts: usize,
+    /// Name of the machine, see [`ReactorBuilder::name`]
+    pub name:     Option<String>,
+}
+
+/// Supervision policy of a [`Reactor`].
+///
+/// Panic in any method of supervised `FSM` is caught and, according to
+/// [`Restart`] strategy, machine is restarted in the same thread, keeping its
+/// event queue and all senders valid. Events queued meanwhile are processed
+/// by the restarted machine; the offending one is dropped.
+///
+/// Supervisor gives up when strategy is `Escalate`, when restart limit is
+/// exceeded or when restart itself panics. Escalation hook is then called and
+/// the panic propagates, so machine ends as with no supervision at all.
+///
+/// # Example
+/// ```
+/// # use pakr_fsm::*;
+/// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// # enum MyEv { Boom, Ping }
+/// # #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+/// # struct MyState;
+/// # struct MyFSM;
+/// # impl FSM for MyFSM {
+/// #     type Event = MyEv;
+/// #     type Response = &'static str;
+/// #     type State = MyState;
+/// #     fn new() -> Self { Self {} }
+/// #     fn trasnsit(&mut self, _: &MyState, ev: &MyEv) -> (Option<MyState>, Option<&'static str>) {
+/// #         match ev {
+/// #             MyEv::Boom => panic!("boom"),
+/// #             MyEv::Ping => (Some(MyState), Some("pong")),
+/// #         }
+/// #     }
+/// #     fn respond(&mut self, _: &MyState, _: &Option<MyState>, _: &&'static str) {}
+/// # }
+/// use std::{
+///     sync::{Arc, Mutex},
+///     time::Duration,
+/// };
+///
+/// let panics = Arc::new(Mutex::new(Vec::new()));
+/// let log = panics.clone();
+///
+/// let supervisor = Supervisor::<MyFSM>::new(Restart::Fresh)
+///     .max_restarts(1, Duration::from_secs(60))
+///     .on_panic(move |report| {
+///         let name = report.name.as_deref().unwrap_or("?");
+///         log.lock().unwrap().push(format!("{}: {}", name, report.message));
+///     });
+/// let fsm = ReactorBuilder::new()
+///     .name("worker")
+///     .supervisor(supervisor)
+///     .spawn()
+///     .unwrap();
+///
+/// assert_eq!(fsm.ask(MyEv::Boom), Err(ReactorError::NoReply));
+/// assert_eq!(fsm.ask(MyEv::Ping), Ok(Some("pong")));
+/// fsm.send(MyEv::Boom).unwrap();
+/// assert_eq!(fsm.join(), Err(ReactorError::MachinePanicked("worker: boom".into())));
+/// assert_eq!(*panics.lock().unwrap(), ["worker: boom", "worker: boom"]);
+/// ```
+pub struct Supervisor<F: FSM> {
+    restart:  Restart,
+    limit:    Option<(usize, Duration)>,
+    recent:   VecDeque<Instant>,
+    restarts: usize,
+    on_panic: Option<PanicHook<F>>,
+    escalate: Option<EscalateHook<F>>,
+    /// State machine starts in
+    initial:  F::State,
+    name:     Option<String>,
+}
+
+impl<F: FSM + 'static> Supervisor<F> {
+    /// Create supervisor using given strategy, with no restart limit.
+    pub fn new(restart: Restart) -> Self {
+        Self {
+            restart,
+            limit: None,
+            recent: VecDeque::new(),
+            restarts: 0,
+            on_panic: None,
+            escalate: None,
+            initial: F::State::default(),
+            name: None,
+        }
+    }
+
+    /// Give up after more than `count` restarts within `window`, measured by
+    /// clock of the machine.
+    pub fn max_restarts(mut self, count: usize, window: Duration) -> Self {
+        self.limit = Some((count, window));
+        self
+    }
+
+    /// Call `hook` on every panic of the machine, before deciding on restart.
+    pub fn on_panic(mut self, hook: impl FnMut(&PanicReport<F>) + Send + 'static) -> Self {
+        self.on_panic = Some(Box::new(hook));
+        self
+    }
+
+    /// Call `hook` with the last panic once supervisor gives up, e.g. to notify
+    /// a parent machine.
+    pub fn escalate_to(mut self, hook: impl FnOnce(PanicReport<F>) + Send + 'static) -> Self {
+        self.escalate = Some(Box::new(hook));
+        self
+    }
+
+    /// Spawn supervised `Reactor`. For other settings use
+    /// [`ReactorBuilder::supervisor`].
+    pub fn spawn(self) -> Reactor<F> {
+        ReactorBuilder::new()
+            .supervisor(self)
+            .spawn()
+            .expect("failed to spawn thread")
+    }
+
+    /// Set state machine starts in
+    pub(crate) fn start_in(&mut self, state: F::State) { self.initial = state }
+
+    /// Set name of the machine, given in reports
+    pub(crate) fn name(&mut self, name: Option<String>) { self.name = name }
+
+    /// Handle panic of `machine`. Returns restarted machine, or payload to
+    /// propagate when giving up.
+    pub(crate) fn recover(
+        &mut self,
+        machine: &mut Machine<F>,
+        payload: Box<dyn Any + Send>,
+        clock: &Arc<dyn Clock>,
+        factory: &mut Factory<F>,
+    ) -> Result<Machine<F>, Box<dyn Any + Send>> {
+        let report = PanicReport {
+            message:  error::panic_message(&*payload),
+            event:    machine.take_current(),
+            state:    machine.state().clone(),
+            restarts: self.restarts,
+            name:     self.name.clone(),
+        };
+        self.decide(report, payload, clock, factory)
+    }
+
+    /// Handle panic of machine being started.
+    pub(crate) fn recover_start(
+        &mut self,
+        payload: Box<dyn Any + Send>,
+        clock: &Arc<dyn Clock>,
+        factory: &mut Factory<F>,
+    ) -> Result<Machine<F>, Box<dyn Any + Send>> {
+        let report = PanicReport {
+            message:  error::panic_message(&*payload),
+            event:    None,
+            state:    self.initial.clone(),
+            restarts: self.restarts,
+            name:     self.name.clone(),
+        };
+        self.decide(report, payload, clock, factory)
+    }
+
+    fn decide(
+        &mut self,
+        report: PanicReport<F>,
+        payload: Box<dyn Any + Send>,
+        clock: &Arc<dyn Clock>,
+        factory: &mut Factory<F>,
+    ) -> Result<Machine<F>, Box<dyn Any + Send>> {
+        if let Some(hook) = &mut self.on_panic {
+            hook(&report);
+        }
+
+        let now = clock.now();
+        self.recent.push_back(now);
+        let exhausted = match self.limit {
+            Some((count, window)) => {
+                while self.recent.front().is_some_and(|at| now - *at > window) {
+                    self.recent.pop_front();
+                }
+                self.recent.len() > count
+            }
+            None => false,
+        };
+
+        let state = match self.restart {
+            _ if exhausted => return self.give_up(report, payload),
+            Restart::Escalate => return self.give_up(report, payload),
+            Restart::Fresh => self.initial.clone(),
+            Restart::LastState => report.state.clone(),
+        };
+
+        self.restarts += 1;
+        let restarted = panic::catch_unwind(AssertUnwindSafe(|| {
+            Machine::with_clock(factory(), state.clone(), clock.clone())
+        }));
+        restarted.or_else(|payload| {
+            let report = PanicReport {
+                message: error::panic_message(&*payload),
+                event: None,
+                state,
+                restarts: self.restarts,
+                name: self.name.clone(),
+            };
+            if let Some(hook) = &mut self.on_panic {
+                hook(&report);
+            }
+            self.give_up(report, payload)
+        })
+    }
+
+    fn give_up(
+        &mut self,
+        report: PanicReport<F>,
+        payload: Box<dyn Any + Send>,
+    ) -> Result<Machine<F>, Box<dyn Any + Send>> {
+        if let Some(hook) = self.escalate.take() {
+            hook(report);
+        }
+        Err(payload)
+    }
+}
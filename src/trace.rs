@@ -0,0 +1,92 @@
+//! Reporting transitions of [`Reactor`](crate::Reactor) to `tracing` or `log`,
+//! when enabled by cargo features of the same names.
+
+#[cfg(any(feature = "tracing", feature = "log"))]
+use std::fmt::Debug;
+
+/// `Debug` when any of `tracing` or `log` features is enabled, otherwise
+/// implemented by every type.
+///
+/// Lets events and states of machines be reported, while not requiring
+/// `Debug` when nothing is reported.
+#[cfg(any(feature = "tracing", feature = "log"))]
+pub trait MaybeDebug: Debug {}
+
+#[cfg(any(feature = "tracing", feature = "log"))]
+impl<T: Debug + ?Sized> MaybeDebug for T {}
+
+/// `Debug` when any of `tracing` or `log` features is enabled, otherwise
+/// implemented by every type.
+///
+/// Lets events and states of machines be reported, while not requiring
+/// `Debug` when nothing is reported.
+#[cfg(not(any(feature = "tracing", feature = "log")))]
+pub trait MaybeDebug {}
+
+#[cfg(not(any(feature = "tracing", feature = "log")))]
+impl<T: ?Sized> MaybeDebug for T {}
+
+/// Target of all `tracing` events and `log` records
+#[cfg(any(feature = "tracing", feature = "log"))]
+const TARGET: &str = "pakr_fsm";
+
+/// Start of a step, formatted only when it is going to be reported
+pub(crate) struct Step {
+    #[cfg(any(feature = "tracing", feature = "log"))]
+    from:  String,
+    #[cfg(any(feature = "tracing", feature = "log"))]
+    event: String,
+}
+
+fn enabled() -> bool {
+    #[cfg(feature = "tracing")]
+    if tracing::enabled!(target: TARGET, tracing::Level::DEBUG) {
+        return true;
+    }
+    #[cfg(feature = "log")]
+    if log::log_enabled!(target: TARGET, log::Level::Debug) {
+        return true;
+    }
+    false
+}
+
+/// Capture state and event of a step about to happen, if anyone listens.
+#[allow(unused_variables)]
+pub(crate) fn begin<S: MaybeDebug, E: MaybeDebug>(from: &S, ev: &E) -> Option<Step> {
+    if !enabled() {
+        return None;
+    }
+    #[cfg(any(feature = "tracing", feature = "log"))]
+    let step = Step {
+        from:  format!("{:?}", from),
+        event: format!("{:?}", ev),
+    };
+    #[cfg(not(any(feature = "tracing", feature = "log")))]
+    let step = Step {};
+    Some(step)
+}
+
+/// Report completed step of machine `name`.
+#[allow(unused_variables)]
+pub(crate) fn end<S: MaybeDebug>(name: &str, step: Step, to: Option<&S>, responded: bool) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        target: TARGET,
+        machine = name,
+        from = %step.from,
+        event = %step.event,
+        to = ?to,
+        responded,
+        "transition"
+    );
+    #[cfg(feature = "log")]
+    log::debug!(
+        target: TARGET,
+        "{}: {} --{}--> {:?}{}",
+        name,
+        step.from,
+        step.event,
+        to,
+        if responded { " with response" } else { "" }
+    );
+}
@@ -0,0 +1,198 @@
+//! Sharing current state of a running machine with other threads.
+
+use crate::{Clock, ReactorError};
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+struct Slot<S> {
+    value:   S,
+    version: u64,
+    closed:  bool,
+    /// When `value` was published
+    since:   Instant,
+}
+
+struct Cell<S> {
+    slot:    Mutex<Slot<S>>,
+    changed: Condvar,
+    clock:   Arc<dyn Clock>,
+}
+
+/// Writing end, owned by the machine thread. Dropping it (also by unwinding)
+/// wakes all watchers.
+pub(crate) struct Publisher<S> {
+    cell:  Arc<Cell<S>>,
+    /// Keeps clock subscription alive
+    _wake: Arc<dyn Fn() + Send + Sync>,
+}
+
+impl<S: Clone + Eq + Send + 'static> Publisher<S> {
+    pub(crate) fn new(value: S, clock: Arc<dyn Clock>) -> Self {
+        let cell = Arc::new(Cell {
+            slot: Mutex::new(Slot {
+                value,
+                version: 0,
+                closed: false,
+                since: clock.now(),
+            }),
+            changed: Condvar::new(),
+            clock,
+        });
+
+        // Let waiters recheck deadlines when clock jumps
+        let weak = Arc::downgrade(&cell);
+        let wake: Arc<dyn Fn() + Send + Sync> = Arc::new(move || {
+            if let Some(cell) = weak.upgrade() {
+                let _slot = cell.slot.lock();
+                cell.changed.notify_all();
+            }
+        });
+        cell.clock.subscribe(Arc::downgrade(&wake));
+
+        Self {
+            cell,
+            _wake: wake,
+        }
+    }
+
+    /// Store new state, waking watchers if it differs from the previous one.
+    pub(crate) fn publish(&self, value: &S) {
+        let mut slot = self.cell.slot.lock().unwrap();
+        if slot.value != *value {
+            slot.value = value.clone();
+            slot.version += 1;
+            slot.since = self.cell.clock.now();
+            self.cell.changed.notify_all();
+        }
+    }
+
+    pub(crate) fn watch(&self) -> StateWatch<S> {
+        StateWatch {
+            cell: self.cell.clone(),
+            seen: 0,
+        }
+        .subscribe()
+    }
+}
+
+impl<S> Drop for Publisher<S> {
+    fn drop(&mut self) {
+        if let Ok(mut slot) = self.cell.slot.lock() {
+            slot.closed = true;
+        }
+        self.cell.changed.notify_all();
+    }
+}
+
+/// Subscription to state changes of a [`Reactor`](crate::Reactor).
+///
+/// Obtained with [`Reactor::watch`](crate::Reactor::watch).
+pub struct StateWatch<S> {
+    cell: Arc<Cell<S>>,
+    seen: u64,
+}
+
+impl<S: Clone> StateWatch<S> {
+    /// New subscription, that has seen only the current state
+    pub(crate) fn subscribe(&self) -> Self {
+        Self {
+            cell: self.cell.clone(),
+            seen: self.cell.slot.lock().unwrap().version,
+        }
+    }
+
+    /// Current state of the machine. After machine ended, it is the last state
+    /// machine was in.
+    pub fn get(&self) -> S { self.cell.slot.lock().unwrap().value.clone() }
+
+    /// Time machine spent in current state so far, measured by its clock.
+    /// After machine ended, it keeps growing.
+    pub fn time_in_state(&self) -> Duration {
+        let since = self.cell.slot.lock().unwrap().since;
+        self.cell.clock.now().saturating_duration_since(since)
+    }
+
+    /// Checks whether state changed since it was last seen by `changed`.
+    pub fn has_changed(&self) -> bool { self.cell.slot.lock().unwrap().version != self.seen }
+
+    /// Blocks until state changes, returning the new one.
+    ///
+    /// Fails with `MachineTerminated` once machine ended and there is no
+    /// unseen change left.
+    pub fn changed(&mut self) -> Result<S, ReactorError<()>> {
+        let mut slot = self.cell.slot.lock().unwrap();
+        while slot.version == self.seen {
+            if slot.closed {
+                return Err(ReactorError::MachineTerminated(()));
+            }
+            slot = self.cell.changed.wait(slot).unwrap();
+        }
+        self.seen = slot.version;
+        Ok(slot.value.clone())
+    }
+
+    /// Same as `changed`, but fails with `Timeout` after `timeout`, measured
+    /// by clock of the machine.
+    ///
+    /// # Example
+    /// ```
+    /// # use pakr_fsm::*;
+    /// # #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    /// # struct MyEv;
+    /// # #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    /// # struct MyState;
+    /// # struct MyFSM;
+    /// # impl FSM for MyFSM {
+    /// #     type Event = MyEv;
+    /// #     type Response = ();
+    /// #     type State = MyState;
+    /// #     fn new() -> Self { Self }
+    /// #     fn trasnsit(&mut self, _: &MyState, _: &MyEv) -> (Option<MyState>, Option<()>) {
+    /// #         (Some(MyState), None)
+    /// #     }
+    /// #     fn respond(&mut self, _: &MyState, _: &Option<MyState>, _: &()) {}
+    /// # }
+    /// use std::{thread, time::Duration};
+    ///
+    /// let clock = ManualClock::new();
+    /// let fsm = Reactor::<MyFSM>::with_clock(clock.clone());
+    /// let mut watch = fsm.watch();
+    ///
+    /// let waiting = thread::spawn(move || watch.changed_timeout(Duration::from_secs(3600)));
+    /// while !waiting.is_finished() {
+    ///     clock.advance(Duration::from_secs(3600));
+    ///     thread::yield_now();
+    /// }
+    /// assert_eq!(waiting.join().unwrap(), Err(ReactorError::Timeout(())));
+    /// ```
+    pub fn changed_timeout(&mut self, timeout: Duration) -> Result<S, ReactorError<()>> {
+        let clock = &self.cell.clock;
+        let deadline = clock.now() + timeout;
+        let mut slot = self.cell.slot.lock().unwrap();
+        while slot.version == self.seen {
+            if slot.closed {
+                return Err(ReactorError::MachineTerminated(()));
+            }
+            if clock.now() >= deadline {
+                return Err(ReactorError::Timeout(()));
+            }
+            slot = match clock.wait_for(deadline) {
+                Some(timeout) => self.cell.changed.wait_timeout(slot, timeout).unwrap().0,
+                None => self.cell.changed.wait(slot).unwrap(),
+            };
+        }
+        self.seen = slot.version;
+        Ok(slot.value.clone())
+    }
+}
+
+impl<S> Clone for StateWatch<S> {
+    fn clone(&self) -> Self {
+        Self {
+            cell: self.cell.clone(),
+            seen: self.seen,
+        }
+    }
+}